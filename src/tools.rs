@@ -1,3 +1,20 @@
 pub mod helper;
 pub mod defs;
-pub mod db_impl;
\ No newline at end of file
+pub mod view;
+pub mod db_impl;
+pub mod error;
+pub mod pager;
+pub mod cache;
+pub mod mmap;
+pub mod wal;
+pub mod journal;
+pub mod lock;
+pub mod freelist;
+pub mod ptrmap;
+pub mod btree;
+pub mod cursor;
+pub mod schema;
+pub mod ddl;
+pub mod database;
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,867 @@
+use crate::tools::{
+    cursor::MAX_DEPTH,
+    db_impl::expected_table_page,
+    defs::*,
+    error::{Error, Result},
+    helper::{read_u32, read_var_int, write_u16, write_u32, write_var_int},
+    pager::Pager,
+    ptrmap::{has_pointer_map, lock_byte_page},
+    view::{PageKind, PageRef},
+};
+use std::collections::BTreeMap;
+
+//######################################################
+// table b-tree writes
+//######################################################
+
+/// adds a row to the table b-tree rooted at `root_page` and commits it.
+/// `payload` is the serialized record.
+pub fn insert(pager: &mut Pager, root_page: u32, row_id: i64, payload: &[u8]) -> Result<()> {
+    pager.check_writable()?;
+    // pointer map entries would have to follow every page that moves
+    if has_pointer_map(pager.header()) {
+        return Err(Error::UnsupportedFeature("writing to an auto-vacuum database".into()));
+    }
+    let mut transaction = Transaction::new(pager)?;
+    transaction.insert(root_page, row_id, payload)?;
+    transaction.commit()
+}
+
+/// a table b-tree page taken apart into its cells, written back in one piece
+struct Node {
+    page: u32,
+    kind: PageKind,
+    /// cells as stored, padded to at least 4 bytes like sqlite does
+    cells: Vec<Vec<u8>>,
+    /// 0 on leaf pages
+    right_child: u32,
+}
+
+impl Node {
+    /// the child left of cell `index`, the right-most child past the last cell
+    fn child(&self, index: usize) -> u32 {
+        match self.cells.get(index) {
+            Some(cell) => left_child(cell),
+            None => self.right_child,
+        }
+    }
+
+    /// first cell whose rowid is not below `row_id`
+    fn position(&self, row_id: i64) -> Result<usize> {
+        let (mut low, mut high) = (0, self.cells.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if cell_row_id(self.kind, &self.cells[mid])? < row_id {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        Ok(low)
+    }
+}
+
+/// pages changed by a write, they reach the file only on commit
+struct Transaction<'a> {
+    pager: &'a mut Pager,
+    header: SqliteHeader,
+    page_count: u32,
+    pages: BTreeMap<u32, Vec<u8>>,
+}
+
+impl<'a> Transaction<'a> {
+    fn new(pager: &'a mut Pager) -> Result<Transaction<'a>> {
+        Ok(Transaction {
+            header: pager.header().clone(),
+            page_count: pager.page_count()?,
+            pager,
+            pages: BTreeMap::new(),
+        })
+    }
+
+    /// updates the header like sqlite does on every write and hands the pages to the pager
+    fn commit(mut self) -> Result<()> {
+        self.header.file_change_counter = self.header.file_change_counter.wrapping_add(1);
+        self.header.version_valid_for = self.header.file_change_counter;
+        self.header.database_size = self.page_count;
+        let mut first = self.page(1)?;
+        let header = self.header.to_bytes();
+        // bytes 72..92 are reserved for expansion, whatever is there stays
+        first[..72].copy_from_slice(&header[..72]);
+        first[92..HEADER_SIZE].copy_from_slice(&header[92..]);
+        self.pages.insert(1, first);
+        self.pager.commit(&self.pages)
+    }
+
+    fn page(&mut self, page: u32) -> Result<Vec<u8>> {
+        if let Some(data) = self.pages.get(&page) {
+            return Ok(data.clone());
+        }
+        if page == 0 || page > self.page_count {
+            return Err(Error::corrupt(format!("reference to page {} of {}", page, self.page_count)));
+        }
+        self.pager.read_raw_page(page as usize).map(|data| data.to_vec())
+    }
+
+    fn insert(&mut self, root_page: u32, row_id: i64, payload: &[u8]) -> Result<()> {
+        // interior pages on the way down with the index of the child taken
+        let mut path: Vec<(Node, usize)> = vec![];
+        let mut node = self.load(root_page)?;
+        while node.kind == PageKind::TableInterior {
+            if path.len() >= MAX_DEPTH {
+                return Err(Error::Corrupt {
+                    page: node.page,
+                    reason: "b-tree too deep".into(),
+                });
+            }
+            let index = node.position(row_id)?;
+            let child = node.child(index);
+            path.push((node, index));
+            node = self.load(child)?;
+        }
+        let index = node.position(row_id)?;
+        if let Some(cell) = node.cells.get(index) {
+            if cell_row_id(node.kind, cell)? == row_id {
+                return Err(Error::Constraint(format!("rowid {} already exists", row_id)));
+            }
+        }
+        let cell = self.leaf_cell(row_id, payload)?;
+        node.cells.insert(index, cell);
+        self.balance(node, path, index)
+    }
+
+    /// writes `node` back, first splitting it and its ancestors as long as they overflow
+    fn balance(&mut self, mut node: Node, mut path: Vec<(Node, usize)>, inserted: usize) -> Result<()> {
+        // position of the new cell while it is still on `node`
+        let mut inserted = Some(inserted);
+        loop {
+            if self.fits(&node) {
+                return self.store(&node);
+            }
+            match path.pop() {
+                None => {
+                    let child = self.balance_deeper(&mut node)?;
+                    // a child of page 1 has the room of the database header on top and may
+                    // take all the cells, the root then keeps just its right-most pointer
+                    if self.fits(&child) {
+                        self.store(&child)?;
+                        return self.store(&node);
+                    }
+                    path.push((node, 0));
+                    node = child;
+                }
+                Some((mut parent, index)) => {
+                    // appending to the right-most leaf just starts a new one
+                    let quick = node.kind == PageKind::TableLeaf
+                        && inserted == Some(node.cells.len() - 1)
+                        && index == parent.cells.len()
+                        && parent.page != 1;
+                    if quick {
+                        self.balance_quick(&mut parent, node)?;
+                    } else {
+                        self.balance_nonroot(&mut parent, index, node)?;
+                    }
+                    node = parent;
+                    inserted = None;
+                }
+            }
+        }
+    }
+
+    /// moves the content of the root into a new child, the root keeps its page number
+    fn balance_deeper(&mut self, root: &mut Node) -> Result<Node> {
+        let child = Node {
+            page: self.allocate()?,
+            kind: root.kind,
+            cells: std::mem::take(&mut root.cells),
+            right_child: root.right_child,
+        };
+        root.kind = PageKind::TableInterior;
+        root.right_child = child.page;
+        Ok(child)
+    }
+
+    /// puts the last cell of the right-most leaf `node` on a new leaf after it
+    fn balance_quick(&mut self, parent: &mut Node, mut node: Node) -> Result<()> {
+        let cell = node.cells.pop().ok_or_else(|| Error::corrupt("balancing an empty page"))?;
+        let last = node.cells.last().ok_or_else(|| Error::corrupt("cell larger than a page"))?;
+        let divider = interior_cell(node.page, cell_row_id(node.kind, last)?);
+        let new = Node {
+            page: self.allocate()?,
+            kind: PageKind::TableLeaf,
+            cells: vec![cell],
+            right_child: 0,
+        };
+        self.store(&node)?;
+        self.store(&new)?;
+        parent.cells.push(divider);
+        parent.right_child = new.page;
+        Ok(())
+    }
+
+    /// spreads the cells of `node` and up to two of its siblings over as many pages as
+    /// they need, then replaces their dividers in `parent`, like sqlite's balance_nonroot
+    fn balance_nonroot(&mut self, parent: &mut Node, index: usize, node: Node) -> Result<()> {
+        let count = parent.cells.len();
+        let (first, old) = match count {
+            0 | 1 => (0, count + 1),
+            _ if index == 0 => (0, 3),
+            _ if index == count => (count - 2, 3),
+            _ => (index - 1, 3),
+        };
+        let kind = node.kind;
+        let mut node = Some(node);
+        let mut siblings = vec![];
+        for i in first..first + old {
+            let sibling = match i == index {
+                true => node.take().ok_or_else(|| Error::corrupt("balancing a page twice"))?,
+                false => self.load(parent.child(i))?,
+            };
+            if sibling.kind != kind {
+                return Err(Error::Corrupt {
+                    page: sibling.page,
+                    reason: "b-tree siblings of different page types".into(),
+                });
+            }
+            siblings.push(sibling);
+        }
+
+        // the cells of all siblings in key order, on interior pages with the
+        // dividers brought down between them
+        let leaf = kind == PageKind::TableLeaf;
+        let dividers: Vec<Vec<u8>> = parent.cells.drain(first..first + old - 1).collect();
+        let right_child = siblings[old - 1].right_child;
+        let mut cells = vec![];
+        for (i, sibling) in siblings.iter_mut().enumerate() {
+            cells.append(&mut sibling.cells);
+            if !leaf && i < old - 1 {
+                let mut divider = dividers[i].clone();
+                write_u32(&mut divider, 0, sibling.right_child);
+                cells.push(divider);
+            }
+        }
+
+        let usable_space = self.header.usable_size() - kind.header_size();
+        // the cells overflowed one page, so there are always at least two and
+        // the parent keeps a divider
+        let ends = distribute(&cells, usable_space, leaf)?;
+        let mut pages: Vec<u32> = siblings.iter().map(|s| s.page).collect();
+        while pages.len() < ends.len() {
+            pages.push(self.allocate()?);
+        }
+        for page in pages.split_off(ends.len()) {
+            self.free(page)?;
+        }
+        // keeps the leaves in file order for faster scans
+        pages.sort_unstable();
+
+        let mut new_dividers = vec![];
+        let mut start = 0;
+        for (i, (&page, &end)) in pages.iter().zip(&ends).enumerate() {
+            let mut new = Node {
+                page,
+                kind,
+                cells: cells[start..end].to_vec(),
+                right_child,
+            };
+            if i < ends.len() - 1 {
+                if leaf {
+                    let last = cells[..end].last().ok_or_else(|| Error::corrupt("empty page after balancing"))?;
+                    new_dividers.push(interior_cell(page, cell_row_id(kind, last)?));
+                    start = end;
+                } else {
+                    // the cell after the page moves up, its left child becomes the right-most one
+                    new.right_child = left_child(&cells[end]);
+                    new_dividers.push(interior_cell(page, cell_row_id(kind, &cells[end])?));
+                    start = end + 1;
+                }
+            }
+            self.store(&new)?;
+        }
+
+        // the pointer to the last sibling stays where it was
+        let last = pages[pages.len() - 1];
+        if first + old - 1 == count {
+            parent.right_child = last;
+        } else {
+            write_u32(&mut parent.cells[first], 0, last);
+        }
+        parent.cells.splice(first..first, new_dividers);
+        Ok(())
+    }
+
+    fn fits(&self, node: &Node) -> bool {
+        let used: usize = node.cells.iter().map(|c| c.len() + 2).sum();
+        header_offset(node.page) + node.kind.header_size() + used <= self.header.usable_size()
+    }
+
+    fn load(&mut self, page: u32) -> Result<Node> {
+        let usable_size = self.header.usable_size();
+        let bytes = self.page(page)?;
+        let view = PageRef::new(&bytes, header_offset(page), usable_size).map_err(|e| e.on_page(page))?;
+        if !view.kind().is_table() {
+            return Err(expected_table_page(page));
+        }
+        let cells = view
+            .cells()
+            .map(|cell| cell.map(|c| padded(c.bytes.to_vec())))
+            .collect::<Result<Vec<Vec<u8>>>>()
+            .map_err(|e| e.on_page(page))?;
+        Ok(Node {
+            page,
+            kind: view.kind(),
+            cells,
+            right_child: view.right_most_pointer().unwrap_or(0),
+        })
+    }
+
+    /// writes the page without free blocks, cell content packed at the end of the usable area
+    fn store(&mut self, node: &Node) -> Result<()> {
+        let offset = header_offset(node.page);
+        let mut buf = vec![0u8; self.header.page_size()];
+        if node.page == 1 {
+            buf[..HEADER_SIZE].copy_from_slice(&self.page(1)?[..HEADER_SIZE]);
+        }
+        buf[offset] = match node.kind {
+            PageKind::IndexInterior => 0x02,
+            PageKind::TableInterior => 0x05,
+            PageKind::IndexLeaf => 0x0a,
+            PageKind::TableLeaf => 0x0d,
+        };
+        write_u16(&mut buf, offset + 3, node.cells.len() as u16);
+        if node.kind == PageKind::TableInterior {
+            write_u32(&mut buf, offset + 8, node.right_child);
+        }
+        let mut content = self.header.usable_size();
+        for (i, cell) in node.cells.iter().enumerate() {
+            content -= cell.len();
+            buf[content..content + cell.len()].copy_from_slice(cell);
+            write_u16(&mut buf, offset + node.kind.header_size() + i * 2, content as u16);
+        }
+        // 0 stands for 65536 on an empty page of that size
+        write_u16(&mut buf, offset + 5, content as u16);
+        self.pages.insert(node.page, buf);
+        Ok(())
+    }
+
+    /// the cell of a table leaf, the part of the payload that does not fit goes to overflow pages
+    fn leaf_cell(&mut self, row_id: i64, payload: &[u8]) -> Result<Vec<u8>> {
+        let mut cell = vec![];
+        write_var_int(&mut cell, payload.len() as u64);
+        write_var_int(&mut cell, row_id as u64);
+        let local = local_payload_size(payload.len() as u64, self.header.usable_size(), true);
+        cell.extend_from_slice(&payload[..local]);
+        if local < payload.len() {
+            let first = self.write_overflow(&payload[local..])?;
+            cell.extend_from_slice(&first.to_be_bytes());
+        }
+        Ok(padded(cell))
+    }
+
+    /// stores `data` in a chain of new overflow pages and returns the first one
+    fn write_overflow(&mut self, data: &[u8]) -> Result<u32> {
+        let chunk = self.header.usable_size() - 4;
+        let pages = data
+            .chunks(chunk)
+            .map(|_| self.allocate())
+            .collect::<Result<Vec<u32>>>()?;
+        for (i, part) in data.chunks(chunk).enumerate() {
+            let mut buf = vec![0u8; self.header.page_size()];
+            write_u32(&mut buf, 0, pages.get(i + 1).copied().unwrap_or(0));
+            buf[4..4 + part.len()].copy_from_slice(part);
+            self.pages.insert(pages[i], buf);
+        }
+        Ok(pages[0])
+    }
+
+    /// takes a page off the freelist, or adds one to the end of the file
+    fn allocate(&mut self) -> Result<u32> {
+        let trunk = self.header.first_freelist_trunk_page;
+        if trunk == 0 {
+            self.page_count += 1;
+            if self.page_count == lock_byte_page(&self.header) {
+                self.page_count += 1;
+            }
+            return Ok(self.page_count);
+        }
+        let mut buf = self.page(trunk)?;
+        let leaf_count = read_u32(&buf, &mut 4)?;
+        let page = if leaf_count > 0 {
+            // the last leaf, so the trunk only needs its count updated
+            let leaf = read_u32(&buf, &mut (8 + (leaf_count as usize - 1) * 4))?;
+            write_u32(&mut buf, 4, leaf_count - 1);
+            self.pages.insert(trunk, buf);
+            leaf
+        } else {
+            self.header.first_freelist_trunk_page = read_u32(&buf, &mut 0)?;
+            trunk
+        };
+        if page < 2 || page > self.page_count || self.header.freelist_page_count == 0 {
+            return Err(Error::Corrupt {
+                page: trunk,
+                reason: format!("invalid freelist page {}", page),
+            });
+        }
+        self.header.freelist_page_count -= 1;
+        Ok(page)
+    }
+
+    fn free(&mut self, page: u32) -> Result<()> {
+        let trunk = self.header.first_freelist_trunk_page;
+        // sqlite leaves the last slots of a trunk unused for older versions
+        let max_leaves = (self.header.usable_size() / 4 - 8) as u32;
+        if trunk != 0 {
+            let mut buf = self.page(trunk)?;
+            let leaf_count = read_u32(&buf, &mut 4)?;
+            if leaf_count < max_leaves {
+                write_u32(&mut buf, 8 + leaf_count as usize * 4, page);
+                write_u32(&mut buf, 4, leaf_count + 1);
+                self.pages.insert(trunk, buf);
+                self.header.freelist_page_count += 1;
+                return Ok(());
+            }
+        }
+        // the page becomes the new first trunk
+        let mut buf = vec![0u8; self.header.page_size()];
+        write_u32(&mut buf, 0, trunk);
+        self.pages.insert(page, buf);
+        self.header.first_freelist_trunk_page = page;
+        self.header.freelist_page_count += 1;
+        Ok(())
+    }
+}
+
+/// splits `cells` into pages of at most `usable_space` bytes and returns where each page
+/// ends. On interior pages the cell at the end of a page moves up into the parent.
+/// Pages are packed from the left, then the right ones are filled up from their
+/// left neighbours about as far as sqlite does.
+fn distribute(cells: &[Vec<u8>], usable_space: usize, leaf: bool) -> Result<Vec<usize>> {
+    let size = |range: std::ops::Range<usize>| -> usize { cells[range].iter().map(|c| c.len() + 2).sum() };
+    // where the page after the one ending at `end` starts
+    let next_start = |end: usize| if leaf { end } else { end + 1 };
+
+    let mut ends = vec![];
+    let mut used = 0;
+    let mut i = 0;
+    while i < cells.len() {
+        let cell = cells[i].len() + 2;
+        if cell > usable_space {
+            return Err(Error::corrupt("cell larger than a page"));
+        }
+        if used + cell > usable_space {
+            ends.push(i);
+            i = next_start(i);
+            used = 0;
+            continue;
+        }
+        used += cell;
+        i += 1;
+    }
+    ends.push(cells.len());
+
+    for i in (1..ends.len()).rev() {
+        let left_start = if i == 1 { 0 } else { next_start(ends[i - 2]) };
+        loop {
+            let left_end = ends[i - 1];
+            // the cell that moves over and, on interior pages, the one replacing it as divider
+            let (moved, new_end) = match leaf {
+                true => (left_end - 1, left_end - 1),
+                false => (left_end, left_end - 1),
+            };
+            if new_end <= left_start {
+                break;
+            }
+            let right = size(next_start(left_end)..ends[i]);
+            let left = size(left_start..left_end);
+            let moved_size = cells[moved].len() + 2;
+            let new_left = left - (cells[new_end].len() + 2);
+            if right != 0 && (right + moved_size > new_left || right + moved_size > usable_space) {
+                break;
+            }
+            ends[i - 1] = new_end;
+        }
+    }
+    Ok(ends)
+}
+
+/// page 1 starts with the database header, the b-tree header follows it
+fn header_offset(page: u32) -> usize {
+    if page == 1 {
+        HEADER_SIZE
+    } else {
+        0
+    }
+}
+
+/// sqlite never stores cells shorter than 4 bytes, the minimum size of a free block
+fn padded(mut cell: Vec<u8>) -> Vec<u8> {
+    if cell.len() < 4 {
+        cell.resize(4, 0);
+    }
+    cell
+}
+
+fn interior_cell(left_child: u32, row_id: i64) -> Vec<u8> {
+    let mut cell = left_child.to_be_bytes().to_vec();
+    write_var_int(&mut cell, row_id as u64);
+    padded(cell)
+}
+
+fn left_child(cell: &[u8]) -> u32 {
+    u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]])
+}
+
+/// rowids are stored as the two's complement bits of a signed integer
+fn cell_row_id(kind: PageKind, cell: &[u8]) -> Result<i64> {
+    let mut row_id = 0;
+    match kind {
+        PageKind::TableInterior => {
+            read_var_int(cell, 4, &mut row_id)?;
+        }
+        _ => {
+            let mut payload_size = 0;
+            let offset = read_var_int(cell, 0, &mut payload_size)?;
+            read_var_int(cell, offset, &mut row_id)?;
+        }
+    }
+    Ok(row_id as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::{
+        database::Database,
+        db_impl::{scan_table, with_page},
+        freelist::read_freelist,
+        testing::{writable, Rng, TempDb},
+    };
+    use std::fs;
+
+    const TABLE: (&str, &str) = ("t", "CREATE TABLE t(a INTEGER, b TEXT)");
+
+    fn record(row_id: i64, text_len: usize) -> Record {
+        let letter = (b'a' + row_id.rem_euclid(26) as u8) as char;
+        Record {
+            header_size: 0,
+            body: vec![
+                Column::I64(row_id),
+                Column::Text(Text::new(&letter.to_string().repeat(text_len))),
+            ],
+        }
+    }
+
+    /// inserts the rows in the given order and returns their payloads by rowid
+    fn insert_rows(db: &mut Database, rows: &[(i64, usize)]) -> BTreeMap<i64, Vec<u8>> {
+        let mut expected = BTreeMap::new();
+        for &(row_id, text_len) in rows {
+            let record = record(row_id, text_len);
+            db.insert("t", row_id, &record).unwrap();
+            expected.insert(row_id, record.to_bytes(TextEncoding::Utf8, 4));
+        }
+        expected
+    }
+
+    fn check_rows(db: &mut Database, expected: &BTreeMap<i64, Vec<u8>>) {
+        let mut rows = vec![];
+        db.scan_table("t", |row_id, payload| {
+            rows.push((row_id, payload.to_vec()));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(rows.len(), expected.len());
+        for ((row_id, payload), (expected_id, expected_payload)) in rows.iter().zip(expected) {
+            assert_eq!(row_id, expected_id);
+            assert!(payload == expected_payload, "payload of row {}", row_id);
+        }
+    }
+
+    /// every page of the file is on the freelist or used exactly once by a b-tree,
+    /// as a tree page or an overflow page, and the header agrees with the file size
+    fn check_pages(temp: &TempDb, db: &mut Database) {
+        let roots: Vec<u32> = db.schema().tables().map(|t| t.root_page).collect();
+        check_file(temp, db.pager(), &roots);
+    }
+
+    /// like `check_pages` with the roots besides page 1 given
+    fn check_file(temp: &TempDb, pager: &mut Pager, roots: &[u32]) {
+        let page_count = pager.page_count().unwrap();
+        assert_eq!(pager.header().database_size, page_count);
+        let file_size = fs::metadata(&temp.path).unwrap().len();
+        assert_eq!(file_size, page_count as u64 * pager.header().page_size() as u64);
+
+        let mut used = read_freelist(pager).unwrap().pages();
+        assert_eq!(used.len(), pager.header().freelist_page_count as usize);
+        for &root in [1].iter().chain(roots) {
+            tree_pages(pager, root, &mut used);
+        }
+        used.sort_unstable();
+        assert_eq!(used, (1..=page_count).collect::<Vec<u32>>());
+    }
+
+    fn tree_pages(pager: &mut Pager, page: u32, used: &mut Vec<u32>) {
+        used.push(page);
+        let (children, overflow) = with_page(pager, page, |view| {
+                let mut children = vec![];
+                let mut overflow = vec![];
+                for cell in view.cells() {
+                    let cell = cell?;
+                    children.extend(cell.left_child_pointer);
+                    if cell.overflow_page != 0 {
+                        overflow.push(cell.overflow_page);
+                    }
+                }
+                children.extend(view.right_most_pointer());
+                Ok((children, overflow))
+            })
+            .unwrap();
+        for mut next in overflow {
+            while next != 0 {
+                used.push(next);
+                next = read_u32(&pager.read_raw_page(next as usize).unwrap(), &mut 0).unwrap();
+            }
+        }
+        for child in children {
+            tree_pages(pager, child, used);
+        }
+    }
+
+    /// number of levels below and including `page`
+    fn depth(db: &mut Database, page: u32) -> usize {
+        let child = db
+            .with_page(page, |view| match view.kind() {
+                PageKind::TableInterior => view.cell(0).map(|c| c.left_child_pointer),
+                _ => Ok(None),
+            })
+            .unwrap();
+        1 + child.map_or(0, |c| depth(db, c))
+    }
+
+    #[test]
+    fn insert_splits_root_and_interior_pages() {
+        let temp = TempDb::create("btree-split", 512, &[TABLE]);
+        let mut db = temp.open();
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let mut keys: Vec<i64> = (1..=3000).collect();
+        for i in (1..keys.len()).rev() {
+            keys.swap(i, rng.below(i as u64 + 1) as usize);
+        }
+        let rows: Vec<(i64, usize)> = keys.iter().map(|&k| (k, rng.below(40) as usize)).collect();
+        let expected = insert_rows(&mut db, &rows);
+
+        let root = db.table_root_page("t").unwrap();
+        assert_eq!(root, 2);
+        assert!(depth(&mut db, root) >= 3, "root and interior pages were split");
+        check_rows(&mut db, &expected);
+        check_pages(&temp, &mut db);
+
+        // a fresh connection reads the same file
+        let mut db = temp.open();
+        check_rows(&mut db, &expected);
+        assert!(!temp.sibling("-journal").exists());
+    }
+
+    #[test]
+    fn insert_splits_root_page_1() {
+        let temp = TempDb::create("btree-page-1", 512, &[]);
+        let mut pager = Pager::open_with_options(&temp.path, &writable()).unwrap();
+        // the first split moves the cells to a child with 100 bytes more room than page 1
+        let mut expected = BTreeMap::new();
+        for row_id in 1..=200 {
+            let payload = vec![row_id as u8; 10];
+            insert(&mut pager, 1, row_id, &payload).unwrap();
+            expected.insert(row_id, payload);
+
+            let mut rows = BTreeMap::new();
+            scan_table(&mut pager, 1, &mut |row_id, payload: &[u8]| {
+                rows.insert(row_id, payload.to_vec());
+                Ok(())
+            })
+            .unwrap();
+            assert!(rows == expected, "rows after inserting {}", row_id);
+        }
+        let root_kind = with_page(&mut pager, 1, |view| Ok(view.kind())).unwrap();
+        assert_eq!(root_kind, PageKind::TableInterior);
+        check_file(&temp, &mut pager, &[]);
+    }
+
+    #[test]
+    fn insert_overflow_payloads() {
+        for page_size in [512, 4096] {
+            let temp = TempDb::create(&format!("btree-overflow-{}", page_size), page_size, &[TABLE]);
+            let mut db = temp.open();
+            let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+            // payloads from a few bytes to several overflow pages
+            let rows: Vec<(i64, usize)> = (0..400)
+                .map(|_| (rng.below(1 << 40) as i64, rng.below(page_size as u64 * 5) as usize))
+                .collect();
+            let expected = insert_rows(&mut db, &rows);
+
+            assert!(depth(&mut db, 2) >= 2);
+            check_rows(&mut db, &expected);
+            check_pages(&temp, &mut db);
+            // the decoded record matches too, overflow included
+            let cells = db.read_table("t").unwrap();
+            assert_eq!(cells.len(), expected.len());
+            for cell in cells {
+                assert!(cell.payload == expected[&cell.row_id], "payload of row {}", cell.row_id);
+            }
+        }
+    }
+
+    #[test]
+    fn insert_appends_and_signed_rowids() {
+        let temp = TempDb::create("btree-append", 512, &[TABLE]);
+        let mut db = temp.open();
+        // ascending rowids take the quick path, descending ones always split the left-most leaf
+        let mut rows: Vec<(i64, usize)> = (1..=1500).map(|k| (k, 20)).collect();
+        rows.extend((1..=1500).map(|k| (-k, 20)));
+        rows.extend([(i64::MAX, 5), (i64::MIN, 5), (0, 5)]);
+        let expected = insert_rows(&mut db, &rows);
+
+        check_rows(&mut db, &expected);
+        check_pages(&temp, &mut db);
+        let found = db.find_by_primary_key("t", i64::MIN).unwrap().unwrap();
+        assert_eq!(found.row_id, i64::MIN);
+        let range: Vec<i64> = db
+            .table_range("t", -2..=1, false)
+            .unwrap()
+            .map(|cell| cell.map(|c| c.row_id))
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(range, vec![-2, -1, 0, 1]);
+    }
+
+    #[test]
+    fn insert_duplicate_rowid_changes_nothing() {
+        let temp = TempDb::create("btree-duplicate", 512, &[TABLE]);
+        let mut db = temp.open();
+        let rows: Vec<(i64, usize)> = (0..200).map(|k| (k * 7, 30)).collect();
+        let expected = insert_rows(&mut db, &rows);
+        let before = fs::read(&temp.path).unwrap();
+
+        let err = db.insert("t", 700, &record(700, 10)).unwrap_err();
+        assert!(matches!(err, Error::Constraint(_)), "{}", err);
+        assert_eq!(fs::read(&temp.path).unwrap(), before);
+        check_rows(&mut db, &expected);
+    }
+
+    #[test]
+    fn insert_keeps_the_reserved_header_bytes() {
+        let temp = TempDb::create("btree-reserved", 512, &[TABLE]);
+        let mut data = fs::read(&temp.path).unwrap();
+        data[72..92].fill(0xaa);
+        fs::write(&temp.path, &data).unwrap();
+        let mut db = temp.open();
+        db.insert("t", 1, &record(1, 10)).unwrap();
+        let written = fs::read(&temp.path).unwrap();
+        assert_eq!(written[72..92], [0xaa; 20]);
+        assert_ne!(written[24..28], data[24..28], "the change counter moves on");
+    }
+
+    #[test]
+    fn insert_reuses_free_pages() {
+        let temp = TempDb::create("btree-reuse", 512, &[TABLE]);
+        // more free pages than one trunk holds
+        let free_pages = 150;
+        {
+            let mut pager = Pager::open_with_options(&temp.path, &writable()).unwrap();
+            let mut transaction = Transaction::new(&mut pager).unwrap();
+            let pages: Vec<u32> = (0..free_pages).map(|_| transaction.allocate().unwrap()).collect();
+            for &page in &pages {
+                transaction.pages.insert(page, vec![0; 512]);
+            }
+            for page in pages {
+                transaction.free(page).unwrap();
+            }
+            transaction.commit().unwrap();
+        }
+        let mut db = temp.open();
+        assert_eq!(db.header().freelist_page_count, free_pages);
+        assert_eq!(db.freelist().unwrap().trunk_pages.len(), 2);
+        check_pages(&temp, &mut db);
+
+        let page_count = db.pager().page_count().unwrap();
+        let mut rng = Rng(0x0123_4567_89ab_cdef);
+        let mut expected = BTreeMap::new();
+        while db.header().freelist_page_count > 0 {
+            let row_id = rng.below(1 << 32) as i64;
+            if expected.contains_key(&row_id) {
+                continue;
+            }
+            // some rows spill to overflow pages, which take free pages too
+            expected.extend(insert_rows(&mut db, &[(row_id, rng.below(1200) as usize)]));
+            assert_eq!(db.pager().page_count().unwrap(), page_count, "file grew while pages were free");
+        }
+        expected.extend(insert_rows(&mut db, &[(-1, 1000)]));
+        assert!(db.pager().page_count().unwrap() > page_count);
+        check_rows(&mut db, &expected);
+        check_pages(&temp, &mut db);
+    }
+
+    #[test]
+    fn balance_frees_pages_it_no_longer_needs() {
+        let temp = TempDb::create("btree-balance-free", 512, &[TABLE]);
+        let mut db = temp.open();
+        // the last row makes three leaves fit on two
+        let rows = [
+            (374, 16),
+            (45, 127),
+            (37, 89),
+            (471, 230),
+            (365, 141),
+            (117, 50),
+            (799, 37),
+            (546, 181),
+            (351, 364),
+            (761, 134),
+            (779, 209),
+            (914, 132),
+            (606, 219),
+            (51, 234),
+            (174, 366),
+            (571, 91),
+            (989, 194),
+            (433, 359),
+            (656, 118),
+        ];
+        let mut expected = insert_rows(&mut db, &rows[..18]);
+        assert_eq!(db.header().freelist_page_count, 0);
+        expected.extend(insert_rows(&mut db, &rows[18..]));
+        assert_eq!(db.header().freelist_page_count, 1);
+        check_rows(&mut db, &expected);
+        check_pages(&temp, &mut db);
+
+        // the next split takes the freed page instead of growing the file
+        let page_count = db.pager().page_count().unwrap();
+        expected.extend(insert_rows(&mut db, &[(1000, 400), (1001, 400)]));
+        assert_eq!(db.header().freelist_page_count, 0);
+        assert_eq!(db.pager().page_count().unwrap(), page_count + 1);
+        check_rows(&mut db, &expected);
+        check_pages(&temp, &mut db);
+    }
+
+    #[test]
+    fn freelist_trunks_fill_and_drain() {
+        let temp = TempDb::create("btree-freelist", 512, &[TABLE]);
+        let mut pager = Pager::open_with_options(&temp.path, &writable()).unwrap();
+        let mut transaction = Transaction::new(&mut pager).unwrap();
+        assert_eq!(transaction.page_count, 2);
+        let mut pages: Vec<u32> = (0..300).map(|_| transaction.allocate().unwrap()).collect();
+        assert_eq!(pages, (3..303).collect::<Vec<u32>>());
+        for &page in &pages {
+            transaction.free(page).unwrap();
+        }
+        // 120 leaves per trunk on 512 byte pages, so 3 trunks hold 297 leaves
+        assert_eq!(transaction.header.freelist_page_count, 300);
+        let mut reused: Vec<u32> = (0..300).map(|_| transaction.allocate().unwrap()).collect();
+        assert_eq!(transaction.header.freelist_page_count, 0);
+        assert_eq!(transaction.header.first_freelist_trunk_page, 0);
+        assert_eq!(transaction.page_count, 302);
+        reused.sort_unstable();
+        pages.sort_unstable();
+        assert_eq!(reused, pages);
+        // the file grows again once the freelist is empty
+        assert_eq!(transaction.allocate().unwrap(), 303);
+    }
+}
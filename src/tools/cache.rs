@@ -0,0 +1,102 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+//######################################################
+// page cache
+//######################################################
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// pages and bytes currently held
+    pub pages: usize,
+    pub bytes: usize,
+}
+
+/// least recently used raw pages, bounded by the total size of the cached pages.
+/// Pages are shared with the readers, a hit hands out another reference.
+pub struct PageCache {
+    capacity: usize,
+    /// page number to content and the tick of its last use
+    pages: HashMap<u32, (Arc<[u8]>, u64)>,
+    /// tick of last use to page number, the first entry is the next to evict
+    lru: BTreeMap<u64, u32>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+impl PageCache {
+    /// a capacity of 0 disables caching
+    pub fn new(capacity: usize) -> PageCache {
+        PageCache {
+            capacity,
+            pages: HashMap::new(),
+            lru: BTreeMap::new(),
+            tick: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn get(&mut self, page: u32) -> Option<Arc<[u8]>> {
+        self.tick += 1;
+        match self.pages.get_mut(&page) {
+            Some((data, last_use)) => {
+                self.lru.remove(last_use);
+                self.lru.insert(self.tick, page);
+                *last_use = self.tick;
+                self.stats.hits += 1;
+                Some(Arc::clone(data))
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, page: u32, data: Arc<[u8]>) {
+        if data.len() > self.capacity {
+            return;
+        }
+        self.remove(page);
+        while self.stats.bytes + data.len() > self.capacity {
+            let (_, oldest) = match self.lru.pop_first() {
+                Some(e) => e,
+                None => break,
+            };
+            if let Some((old, _)) = self.pages.remove(&oldest) {
+                self.stats.bytes -= old.len();
+                self.stats.evictions += 1;
+            }
+        }
+        self.tick += 1;
+        self.stats.bytes += data.len();
+        self.pages.insert(page, (data, self.tick));
+        self.lru.insert(self.tick, page);
+        self.stats.pages = self.pages.len();
+    }
+
+    pub fn remove(&mut self, page: u32) {
+        if let Some((data, last_use)) = self.pages.remove(&page) {
+            self.lru.remove(&last_use);
+            self.stats.bytes -= data.len();
+            self.stats.pages = self.pages.len();
+        }
+    }
+
+    /// drops every page, the hit and miss counters are kept
+    pub fn clear(&mut self) {
+        self.pages.clear();
+        self.lru.clear();
+        self.stats.bytes = 0;
+        self.stats.pages = 0;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+}
@@ -0,0 +1,619 @@
+use std::{
+    cmp::Ordering,
+    ops::{Bound, RangeBounds},
+};
+
+use crate::tools::{
+    db_impl::{expected_table_page, read_page},
+    defs::*,
+    error::{Error, Result},
+    pager::Pager,
+};
+
+/// sqlite itself refuses b-trees deeper than this, deeper means a cycle
+pub(crate) const MAX_DEPTH: usize = 20;
+
+//######################################################
+// table cursor
+//######################################################
+
+/// walks a table b-tree in rowid order, holding only the pages on the
+/// path from the root to the current leaf.
+///
+/// The cursor sits in a gap between two rows: `next` returns the row after
+/// the gap and moves past it, `prev` returns the row before it. A fresh
+/// cursor starts before the first row for `next` and after the last row
+/// for `prev`.
+pub struct TableCursor<'a> {
+    pager: &'a mut Pager,
+    root_page: u32,
+    /// interior pages on the path with the index of the child we are in,
+    /// the child after the last cell is the right most pointer
+    interior: Vec<(u32, TableBTreeInteriorPage, usize)>,
+    /// current leaf and the gap position within its cells
+    leaf: Option<(u32, TableBTreeLeafPage, usize)>,
+}
+
+impl<'a> TableCursor<'a> {
+    pub fn new(pager: &'a mut Pager, root_page: u32) -> TableCursor<'a> {
+        TableCursor {
+            pager,
+            root_page,
+            interior: vec![],
+            leaf: None,
+        }
+    }
+
+    /// moves before the first row
+    pub fn first(&mut self) -> Result<()> {
+        self.interior.clear();
+        self.descend(self.root_page, false)
+    }
+
+    /// moves after the last row
+    pub fn last(&mut self) -> Result<()> {
+        self.interior.clear();
+        self.descend(self.root_page, true)
+    }
+
+    /// moves before the first row with a rowid >= `row_id`,
+    /// returns true when that row has exactly the requested rowid
+    pub fn seek(&mut self, row_id: i64) -> Result<bool> {
+        self.seek_impl(row_id, false)
+    }
+
+    /// moves after the last row with a rowid <= `row_id`
+    pub fn seek_after(&mut self, row_id: i64) -> Result<()> {
+        self.seek_impl(row_id, true)?;
+        Ok(())
+    }
+
+    fn seek_impl(&mut self, row_id: i64, after: bool) -> Result<bool> {
+        self.interior.clear();
+        self.leaf = None;
+        // rows for which this holds come before the gap we are looking for
+        let before = |r: i64| if after { r <= row_id } else { r < row_id };
+        let mut page_number = self.root_page;
+        loop {
+            match read_page(self.pager, page_number as usize)? {
+                Page::TableBTreeInteriorPage(p) => {
+                    let child = p.cells.partition_point(|c| before(c.row_id));
+                    let next = child_pointer(&p, child);
+                    self.push_interior(page_number, p, child)?;
+                    page_number = next;
+                }
+                Page::TableBTreeLeafPage(p) => {
+                    let gap = p.cells.partition_point(|c| before(c.row_id));
+                    let exact = p.cells.get(gap).is_some_and(|c| c.row_id == row_id);
+                    let at_end = gap == p.cells.len();
+                    self.leaf = Some((page_number, p, gap));
+                    if at_end {
+                        // the row we want is the first one of the following leaf
+                        if let Some(c) = self.peek_next()? {
+                            return Ok(c == row_id);
+                        }
+                    }
+                    return Ok(exact);
+                }
+                _ => return Err(expected_table_page(page_number)),
+            }
+        }
+    }
+
+    /// rowid of the row `next` would return, without moving
+    fn peek_next(&mut self) -> Result<Option<i64>> {
+        match self.step_forward()? {
+            Some(cell) => {
+                let row_id = cell.row_id;
+                if let Some((_, _, gap)) = &mut self.leaf {
+                    *gap -= 1;
+                }
+                Ok(Some(row_id))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// row after the gap, moving past it. Not `Iterator::next` so that it
+    /// pairs with `prev`, use `rows` for an iterator
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<TableBTreeLeafCell>> {
+        if self.leaf.is_none() {
+            self.first()?;
+        }
+        Ok(self.step_forward()?.cloned())
+    }
+
+    /// row before the gap, moving back over it
+    pub fn prev(&mut self) -> Result<Option<TableBTreeLeafCell>> {
+        if self.leaf.is_none() {
+            self.last()?;
+        }
+        Ok(self.step_backward()?.cloned())
+    }
+
+    /// iterates forward from the current position
+    pub fn rows(&mut self) -> TableRows<'_, 'a> {
+        TableRows { cursor: self }
+    }
+
+    fn step_forward(&mut self) -> Result<Option<&TableBTreeLeafCell>> {
+        loop {
+            let (_, leaf, gap) = match &self.leaf {
+                Some(l) => l,
+                None => return Ok(None),
+            };
+            if *gap < leaf.cells.len() {
+                break;
+            }
+            // climb to the deepest ancestor that still has a child to the right,
+            // staying put at the end of the table when there is none
+            let level = match self
+                .interior
+                .iter()
+                .rposition(|(_, p, child)| *child < p.cells.len())
+            {
+                Some(level) => level,
+                None => return Ok(None),
+            };
+            self.interior.truncate(level + 1);
+            let (_, p, child) = self.interior.last_mut().unwrap();
+            *child += 1;
+            let next = child_pointer(p, *child);
+            self.descend(next, false)?;
+        }
+        let (_, leaf, gap) = self.leaf.as_mut().unwrap();
+        *gap += 1;
+        Ok(Some(&leaf.cells[*gap - 1]))
+    }
+
+    fn step_backward(&mut self) -> Result<Option<&TableBTreeLeafCell>> {
+        loop {
+            let (_, _, gap) = match &self.leaf {
+                Some(l) => l,
+                None => return Ok(None),
+            };
+            if *gap > 0 {
+                break;
+            }
+            let level = match self.interior.iter().rposition(|(_, _, child)| *child > 0) {
+                Some(level) => level,
+                None => return Ok(None),
+            };
+            self.interior.truncate(level + 1);
+            let (_, p, child) = self.interior.last_mut().unwrap();
+            *child -= 1;
+            let next = child_pointer(p, *child);
+            self.descend(next, true)?;
+        }
+        let (_, leaf, gap) = self.leaf.as_mut().unwrap();
+        *gap -= 1;
+        Ok(Some(&leaf.cells[*gap]))
+    }
+
+    /// follows the left most (or right most) children down to a leaf
+    fn descend(&mut self, page_number: u32, to_end: bool) -> Result<()> {
+        let mut page_number = page_number;
+        loop {
+            match read_page(self.pager, page_number as usize)? {
+                Page::TableBTreeInteriorPage(p) => {
+                    let child = if to_end { p.cells.len() } else { 0 };
+                    let next = child_pointer(&p, child);
+                    self.push_interior(page_number, p, child)?;
+                    page_number = next;
+                }
+                Page::TableBTreeLeafPage(p) => {
+                    let gap = if to_end { p.cells.len() } else { 0 };
+                    self.leaf = Some((page_number, p, gap));
+                    return Ok(());
+                }
+                _ => return Err(expected_table_page(page_number)),
+            }
+        }
+    }
+
+    fn push_interior(&mut self, page_number: u32, page: TableBTreeInteriorPage, child: usize) -> Result<()> {
+        if self.interior.len() >= MAX_DEPTH {
+            return Err(Error::Corrupt {
+                page: page_number,
+                reason: "b-tree too deep".into(),
+            });
+        }
+        self.interior.push((page_number, page, child));
+        Ok(())
+    }
+}
+
+pub struct TableRows<'c, 'a> {
+    cursor: &'c mut TableCursor<'a>,
+}
+
+impl Iterator for TableRows<'_, '_> {
+    type Item = Result<TableBTreeLeafCell>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor.next().transpose()
+    }
+}
+
+fn child_pointer(page: &TableBTreeInteriorPage, child: usize) -> u32 {
+    match page.cells.get(child) {
+        Some(cell) => cell.left_child_pointer,
+        None => page.header.right_most_pointer,
+    }
+}
+
+//######################################################
+// table range
+//######################################################
+
+/// rows whose rowid lies within the bounds, in ascending or descending order
+pub struct TableRange<'a> {
+    cursor: TableCursor<'a>,
+    start: Bound<i64>,
+    end: Bound<i64>,
+    reverse: bool,
+    positioned: bool,
+}
+
+impl<'a> TableRange<'a> {
+    pub fn new<R: RangeBounds<i64>>(pager: &'a mut Pager, root_page: u32, range: R, reverse: bool) -> TableRange<'a> {
+        TableRange {
+            cursor: TableCursor::new(pager, root_page),
+            start: range.start_bound().cloned(),
+            end: range.end_bound().cloned(),
+            reverse,
+            positioned: false,
+        }
+    }
+
+    fn position(&mut self) -> Result<()> {
+        if self.reverse {
+            match self.end {
+                Bound::Included(k) => self.cursor.seek_after(k),
+                Bound::Excluded(k) => self.cursor.seek(k).map(|_| ()),
+                Bound::Unbounded => self.cursor.last(),
+            }
+        } else {
+            match self.start {
+                Bound::Included(k) => self.cursor.seek(k).map(|_| ()),
+                Bound::Excluded(k) => self.cursor.seek_after(k),
+                Bound::Unbounded => self.cursor.first(),
+            }
+        }
+    }
+
+    fn next_row(&mut self) -> Result<Option<TableBTreeLeafCell>> {
+        if !self.positioned {
+            self.position()?;
+            self.positioned = true;
+        }
+        let cell = if self.reverse {
+            self.cursor.prev()?
+        } else {
+            self.cursor.next()?
+        };
+        let limit = if self.reverse { self.start } else { self.end };
+        Ok(cell.filter(|c| within_limit(c.row_id.cmp(&bound_value(limit)), limit, self.reverse)))
+    }
+}
+
+impl Iterator for TableRange<'_> {
+    type Item = Result<TableBTreeLeafCell>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_row().transpose()
+    }
+}
+
+fn bound_value<T: Default + Clone>(bound: Bound<T>) -> T {
+    match bound {
+        Bound::Included(v) | Bound::Excluded(v) => v,
+        Bound::Unbounded => T::default(),
+    }
+}
+
+/// whether a value that compares to the limit as `ordering` still lies in the range,
+/// `reverse` tells if the limit is a lower one
+fn within_limit<T>(ordering: Ordering, limit: Bound<T>, reverse: bool) -> bool {
+    let ordering = if reverse { ordering.reverse() } else { ordering };
+    match limit {
+        Bound::Unbounded => true,
+        Bound::Included(_) => ordering != Ordering::Greater,
+        Bound::Excluded(_) => ordering == Ordering::Less,
+    }
+}
+
+//######################################################
+// index cursor
+//######################################################
+
+/// decoded entries of an interior index page together with its child pointers,
+/// `children` has one more element than `keys`
+struct IndexInteriorFrame {
+    keys: Vec<Record>,
+    children: Vec<u32>,
+    child: usize,
+}
+
+/// walks an index b-tree in key order. Entries of interior pages are part of
+/// the sequence, each one sits between the subtrees on its left and right.
+/// Positioning works like `TableCursor`.
+pub struct IndexCursor<'a> {
+    pager: &'a mut Pager,
+    root_page: u32,
+    key_order: KeyOrder,
+    interior: Vec<IndexInteriorFrame>,
+    leaf: Option<(u32, Vec<Record>, usize)>,
+}
+
+impl<'a> IndexCursor<'a> {
+    pub fn new(pager: &'a mut Pager, root_page: u32, key_order: KeyOrder) -> IndexCursor<'a> {
+        IndexCursor {
+            pager,
+            root_page,
+            key_order,
+            interior: vec![],
+            leaf: None,
+        }
+    }
+
+    pub fn first(&mut self) -> Result<()> {
+        self.interior.clear();
+        self.descend(self.root_page, false)
+    }
+
+    pub fn last(&mut self) -> Result<()> {
+        self.interior.clear();
+        self.descend(self.root_page, true)
+    }
+
+    /// moves before the first entry whose leading columns are >= `key`,
+    /// returns true when the leading columns of that entry equal `key`
+    pub fn seek(&mut self, key: &[Column]) -> Result<bool> {
+        self.seek_impl(key, false)?;
+        let exact = match self.next()? {
+            Some(entry) => {
+                self.prev()?;
+                self.key_order.compare_prefix(&entry.body, key) == Ordering::Equal
+            }
+            None => false,
+        };
+        Ok(exact)
+    }
+
+    /// moves after the last entry whose leading columns are <= `key`
+    pub fn seek_after(&mut self, key: &[Column]) -> Result<()> {
+        self.seek_impl(key, true)
+    }
+
+    fn seek_impl(&mut self, key: &[Column], after: bool) -> Result<()> {
+        self.interior.clear();
+        self.leaf = None;
+        let before = |key_order: &KeyOrder, r: &Record| {
+            let ordering = key_order.compare_prefix(&r.body, key);
+            ordering == Ordering::Less || (after && ordering == Ordering::Equal)
+        };
+        let mut page_number = self.root_page;
+        loop {
+            match self.load(page_number)? {
+                (keys, Some(children)) => {
+                    let child = keys.partition_point(|r| before(&self.key_order, r));
+                    let next = children[child];
+                    self.push_interior(page_number, keys, children, child)?;
+                    page_number = next;
+                }
+                (keys, None) => {
+                    let gap = keys.partition_point(|r| before(&self.key_order, r));
+                    self.leaf = Some((page_number, keys, gap));
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// entry after the gap, moving past it
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<Record>> {
+        if self.leaf.is_none() {
+            self.first()?;
+        }
+        let (_, keys, gap) = match &mut self.leaf {
+            Some(l) => l,
+            None => return Ok(None),
+        };
+        if *gap < keys.len() {
+            *gap += 1;
+            return Ok(Some(keys[*gap - 1].clone()));
+        }
+        // the next entry is the separator of the deepest ancestor we can move right in
+        let level = match self.interior.iter().rposition(|f| f.child < f.keys.len()) {
+            Some(level) => level,
+            None => return Ok(None),
+        };
+        self.interior.truncate(level + 1);
+        let frame = self.interior.last_mut().unwrap();
+        let entry = frame.keys[frame.child].clone();
+        frame.child += 1;
+        let next = frame.children[frame.child];
+        self.descend(next, false)?;
+        Ok(Some(entry))
+    }
+
+    /// entry before the gap, moving back over it
+    pub fn prev(&mut self) -> Result<Option<Record>> {
+        if self.leaf.is_none() {
+            self.last()?;
+        }
+        let (_, keys, gap) = match &mut self.leaf {
+            Some(l) => l,
+            None => return Ok(None),
+        };
+        if *gap > 0 {
+            *gap -= 1;
+            return Ok(Some(keys[*gap].clone()));
+        }
+        let level = match self.interior.iter().rposition(|f| f.child > 0) {
+            Some(level) => level,
+            None => return Ok(None),
+        };
+        self.interior.truncate(level + 1);
+        let frame = self.interior.last_mut().unwrap();
+        frame.child -= 1;
+        let entry = frame.keys[frame.child].clone();
+        let next = frame.children[frame.child];
+        self.descend(next, true)?;
+        Ok(Some(entry))
+    }
+
+    /// iterates forward from the current position
+    pub fn rows(&mut self) -> IndexRows<'_, 'a> {
+        IndexRows { cursor: self }
+    }
+
+    fn descend(&mut self, page_number: u32, to_end: bool) -> Result<()> {
+        let mut page_number = page_number;
+        loop {
+            match self.load(page_number)? {
+                (keys, Some(children)) => {
+                    let child = if to_end { keys.len() } else { 0 };
+                    let next = children[child];
+                    self.push_interior(page_number, keys, children, child)?;
+                    page_number = next;
+                }
+                (keys, None) => {
+                    let gap = if to_end { keys.len() } else { 0 };
+                    self.leaf = Some((page_number, keys, gap));
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// decoded entries of a page, plus the child pointers for interior pages
+    fn load(&mut self, page_number: u32) -> Result<(Vec<Record>, Option<Vec<u32>>)> {
+        let encoding = self.pager.header().text_encoding;
+        let decode = |payload: &[u8]| Record::new(payload, encoding).map_err(|e| e.on_page(page_number));
+        match read_page(self.pager, page_number as usize)? {
+            Page::IndexBTreeInteriorPage(p) => {
+                let keys = p.cells.iter().map(|c| decode(&c.payload)).collect::<Result<Vec<_>>>()?;
+                let mut children: Vec<u32> = p.cells.iter().map(|c| c.left_child_pointer).collect();
+                children.push(p.header.right_most_pointer);
+                Ok((keys, Some(children)))
+            }
+            Page::IndexBTreeLeafPage(p) => {
+                let keys = p.cells.iter().map(|c| decode(&c.payload)).collect::<Result<Vec<_>>>()?;
+                Ok((keys, None))
+            }
+            _ => Err(Error::Corrupt {
+                page: page_number,
+                reason: "expected index page, found table page".into(),
+            }),
+        }
+    }
+
+    fn push_interior(&mut self, page: u32, keys: Vec<Record>, children: Vec<u32>, child: usize) -> Result<()> {
+        if self.interior.len() >= MAX_DEPTH {
+            return Err(Error::Corrupt {
+                page,
+                reason: "b-tree too deep".into(),
+            });
+        }
+        self.interior.push(IndexInteriorFrame {
+            keys,
+            children,
+            child,
+        });
+        Ok(())
+    }
+}
+
+pub struct IndexRows<'c, 'a> {
+    cursor: &'c mut IndexCursor<'a>,
+}
+
+impl Iterator for IndexRows<'_, '_> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor.next().transpose()
+    }
+}
+
+//######################################################
+// index range
+//######################################################
+
+/// index entries whose leading columns lie within the bounds. Bounds are
+/// compared on their own length, so `Included(vec![a])` on both ends is a
+/// prefix scan over a multi column index.
+pub struct IndexRange<'a> {
+    cursor: IndexCursor<'a>,
+    start: Bound<Vec<Column>>,
+    end: Bound<Vec<Column>>,
+    reverse: bool,
+    positioned: bool,
+}
+
+impl<'a> IndexRange<'a> {
+    pub fn new(
+        pager: &'a mut Pager,
+        root_page: u32,
+        key_order: KeyOrder,
+        start: Bound<Vec<Column>>,
+        end: Bound<Vec<Column>>,
+        reverse: bool,
+    ) -> IndexRange<'a> {
+        IndexRange {
+            cursor: IndexCursor::new(pager, root_page, key_order),
+            start,
+            end,
+            reverse,
+            positioned: false,
+        }
+    }
+
+    fn position(&mut self) -> Result<()> {
+        if self.reverse {
+            match &self.end {
+                Bound::Included(k) => self.cursor.seek_after(k),
+                Bound::Excluded(k) => self.cursor.seek(k).map(|_| ()),
+                Bound::Unbounded => self.cursor.last(),
+            }
+        } else {
+            match &self.start {
+                Bound::Included(k) => self.cursor.seek(k).map(|_| ()),
+                Bound::Excluded(k) => self.cursor.seek_after(k),
+                Bound::Unbounded => self.cursor.first(),
+            }
+        }
+    }
+
+    fn next_entry(&mut self) -> Result<Option<Record>> {
+        if !self.positioned {
+            self.position()?;
+            self.positioned = true;
+        }
+        let entry = if self.reverse {
+            self.cursor.prev()?
+        } else {
+            self.cursor.next()?
+        };
+        let limit = if self.reverse { &self.start } else { &self.end };
+        Ok(entry.filter(|e| {
+            let ordering = match limit {
+                Bound::Included(k) | Bound::Excluded(k) => self.cursor.key_order.compare_prefix(&e.body, k),
+                Bound::Unbounded => Ordering::Equal,
+            };
+            within_limit(ordering, limit.as_ref(), self.reverse)
+        }))
+    }
+}
+
+impl Iterator for IndexRange<'_> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
@@ -0,0 +1,307 @@
+use crate::tools::{
+    btree,
+    cache::CacheStats,
+    cursor::{IndexCursor, IndexRange, TableCursor, TableRange},
+    db_impl::*,
+    ddl::{parse_create_index, parse_create_table, Generated, IndexDef, Row, TableDef},
+    defs::*,
+    freelist::{freelist_stats, read_freelist, Freelist, FreelistStats},
+    error::{Error, Result},
+    pager::{DatabaseOptions, Pager},
+    ptrmap::{pointer_map_entry, read_pointer_map},
+    schema::{Schema, SchemaEntry},
+    view::{PageRef, RecordView},
+};
+use std::{
+    ops::{Bound, RangeBounds},
+    path::Path,
+};
+
+//######################################################
+// database
+//######################################################
+
+pub struct Database {
+    pager: Pager,
+    schema: Schema,
+}
+
+impl Database {
+    /// opens read-only, see `open_with_options` to allow writes
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Database> {
+        Database::open_with_options(path, &DatabaseOptions::default())
+    }
+
+    /// an interrupted transaction is rolled back first, on disk when writes are allowed
+    pub fn open_with_options<P: AsRef<Path>>(path: P, options: &DatabaseOptions) -> Result<Database> {
+        let mut pager = Pager::open_with_options(path, options)?;
+        let schema = read_first_page(&mut pager)?;
+        Ok(Database { pager, schema })
+    }
+
+    pub fn header(&self) -> &SqliteHeader {
+        self.pager.header()
+    }
+
+    pub fn pager(&mut self) -> &mut Pager {
+        &mut self.pager
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// re-reads sqlite_schema, needed after the schema was changed by another connection
+    pub fn reload_schema(&mut self) -> Result<()> {
+        self.schema = read_first_page(&mut self.pager)?;
+        Ok(())
+    }
+
+    /// picks up changes made by other connections since the last call,
+    /// run at the start of every read like sqlite does when taking its shared lock
+    fn refresh(&mut self) -> Result<()> {
+        let schema_cookie = self.header().schema_cookie;
+        if self.pager.check_for_changes()? && self.header().schema_cookie != schema_cookie {
+            self.reload_schema()?;
+        }
+        Ok(())
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.pager.cache_stats()
+    }
+
+    pub fn table(&self, table: &str) -> Result<&SchemaEntry> {
+        self.schema
+            .table(table)
+            .ok_or_else(|| Error::NotFound(format!("no such table: {}", table)))
+    }
+
+    pub fn index(&self, index: &str) -> Result<&SchemaEntry> {
+        self.schema
+            .index(index)
+            .ok_or_else(|| Error::NotFound(format!("no such index: {}", index)))
+    }
+
+    pub fn table_def(&self, table: &str) -> Result<TableDef> {
+        match &self.table(table)?.sql {
+            Some(sql) => parse_create_table(sql),
+            None => Err(Error::NotFound(format!("no sql for table: {}", table))),
+        }
+    }
+
+    /// automatic indexes created for UNIQUE and PRIMARY KEY constraints have no sql,
+    /// their definition comes from the constraint in the table definition
+    pub fn index_def(&self, index: &str) -> Result<IndexDef> {
+        let entry = self.index(index)?;
+        match &entry.sql {
+            Some(sql) => parse_create_index(sql),
+            None => self
+                .table_def(&entry.tbl_name)?
+                .automatic_index(index)
+                .ok_or_else(|| Error::NotFound(format!("no constraint for automatic index {}", index))),
+        }
+    }
+
+    pub fn table_root_page(&self, table: &str) -> Result<u32> {
+        self.table(table).map(|e| e.root_page)
+    }
+
+    pub fn index_root_page(&self, index: &str) -> Result<u32> {
+        self.index(index).map(|e| e.root_page)
+    }
+
+    pub fn read_page(&mut self, page: usize) -> Result<Page> {
+        self.refresh()?;
+        read_page(&mut self.pager, page)
+    }
+
+    /// inspects a b-tree page in place, see `PageRef`
+    pub fn with_page<T, F>(&mut self, page: u32, f: F) -> Result<T>
+    where
+        F: FnOnce(PageRef<'_>) -> Result<T>,
+    {
+        self.refresh()?;
+        with_page(&mut self.pager, page, f)
+    }
+
+    /// every pointer map entry of an auto-vacuum database, empty for other databases
+    pub fn pointer_map(&mut self) -> Result<Vec<PointerMapEntry>> {
+        self.refresh()?;
+        read_pointer_map(&mut self.pager)
+    }
+
+    pub fn pointer_map_entry(&mut self, page: u32) -> Result<PointerMapEntry> {
+        self.refresh()?;
+        pointer_map_entry(&mut self.pager, page)
+    }
+
+    pub fn freelist(&mut self) -> Result<Freelist> {
+        self.refresh()?;
+        read_freelist(&mut self.pager)
+    }
+
+    /// free page count and its share of the file, useful to decide when to VACUUM
+    pub fn freelist_stats(&mut self) -> Result<FreelistStats> {
+        self.refresh()?;
+        freelist_stats(&mut self.pager)
+    }
+
+    pub fn read_table(&mut self, table: &str) -> Result<Vec<TableBTreeLeafCell>> {
+        self.refresh()?;
+        let root_page = self.table_root_page(table)?;
+        let mut cells: Vec<TableBTreeLeafCell> = vec![];
+        read_table(&mut self.pager, root_page, &mut cells)?;
+        Ok(cells)
+    }
+
+    /// visits the rowid and record payload of every row without copying them,
+    /// decode the payload with `RecordView`
+    pub fn scan_table<F>(&mut self, table: &str, mut f: F) -> Result<()>
+    where
+        F: FnMut(i64, &[u8]) -> Result<()>,
+    {
+        self.refresh()?;
+        let root_page = self.table_root_page(table)?;
+        scan_table(&mut self.pager, root_page, &mut f)
+    }
+
+    /// streams the rows of a table in rowid order
+    pub fn table_cursor(&mut self, table: &str) -> Result<TableCursor<'_>> {
+        self.refresh()?;
+        let root_page = self.table_root_page(table)?;
+        Ok(TableCursor::new(&mut self.pager, root_page))
+    }
+
+    /// rows with a rowid inside `range`, descending when `reverse` is set
+    pub fn table_range<R: RangeBounds<i64>>(&mut self, table: &str, range: R, reverse: bool) -> Result<TableRange<'_>> {
+        self.refresh()?;
+        let root_page = self.table_root_page(table)?;
+        Ok(TableRange::new(&mut self.pager, root_page, range, reverse))
+    }
+
+    /// sort directions and collations of the index columns
+    pub fn index_key_order(&self, index: &str) -> Result<KeyOrder> {
+        let index_def = self.index_def(index)?;
+        let table_def = self.table_def(&self.index(index)?.tbl_name)?;
+        index_def.key_order(&table_def, self.header().text_encoding)
+    }
+
+    pub fn index_cursor(&mut self, index: &str) -> Result<IndexCursor<'_>> {
+        self.refresh()?;
+        let root_page = self.index_root_page(index)?;
+        let key_order = self.index_key_order(index)?;
+        Ok(IndexCursor::new(&mut self.pager, root_page, key_order))
+    }
+
+    /// index entries between `start` and `end`, see `IndexRange` for how shorter keys match
+    pub fn index_range(
+        &mut self,
+        index: &str,
+        start: Bound<Vec<Column>>,
+        end: Bound<Vec<Column>>,
+        reverse: bool,
+    ) -> Result<IndexRange<'_>> {
+        self.refresh()?;
+        let root_page = self.index_root_page(index)?;
+        let key_order = self.index_key_order(index)?;
+        Ok(IndexRange::new(&mut self.pager, root_page, key_order, start, end, reverse))
+    }
+
+    /// reads the whole table with values mapped to the declared column names
+    pub fn read_rows(&mut self, table: &str) -> Result<Vec<Row>> {
+        self.refresh()?;
+        let table_def = self.table_def(table)?;
+        if table_def.without_rowid {
+            return Err(Error::UnsupportedFeature(format!("reading WITHOUT ROWID table {}", table)));
+        }
+        let encoding = self.header().text_encoding;
+        self.read_table(table)?
+            .into_iter()
+            .map(|cell| Ok(table_def.row(cell.row_id, Record::new(&cell.payload, encoding)?)))
+            .collect()
+    }
+
+    /// reads only the named columns of every row, the other values are skipped undecoded
+    pub fn read_columns(&mut self, table: &str, columns: &[&str]) -> Result<Vec<Row>> {
+        self.refresh()?;
+        let table_def = self.table_def(table)?;
+        if table_def.without_rowid {
+            return Err(Error::UnsupportedFeature(format!("reading WITHOUT ROWID table {}", table)));
+        }
+        let positions = columns
+            .iter()
+            .map(|name| {
+                table_def
+                    .column_index(name)
+                    .ok_or_else(|| Error::NotFound(format!("no such column: {}.{}", table, name)))
+            })
+            .collect::<Result<Vec<usize>>>()?;
+        let encoding = self.header().text_encoding;
+        let root_page = self.table_root_page(table)?;
+        let mut rows = vec![];
+        scan_table(&mut self.pager, root_page, &mut |row_id, payload| {
+            let record = RecordView::new(payload, encoding)?;
+            rows.push(table_def.project(row_id, &record, &positions)?);
+            Ok(())
+        })?;
+        Ok(rows)
+    }
+
+    /// adds a row to a rowid table and commits it, see `Pager::commit` for how.
+    /// The record holds a value for every stored column, NULL for an INTEGER PRIMARY KEY.
+    /// Tables with indexes are refused as their entries would be missing.
+    pub fn insert(&mut self, table: &str, row_id: i64, record: &Record) -> Result<()> {
+        self.refresh()?;
+        let table_def = self.table_def(table)?;
+        if table_def.without_rowid {
+            return Err(Error::UnsupportedFeature(format!("inserting into WITHOUT ROWID table {}", table)));
+        }
+        if !self.schema.indexes_for_table(table).is_empty() {
+            return Err(Error::UnsupportedFeature(format!("inserting into table {} with indexes", table)));
+        }
+        let stored = table_def
+            .columns
+            .iter()
+            .filter(|c| c.generated != Some(Generated::Virtual))
+            .count();
+        if record.body.len() > stored {
+            return Err(Error::TypeMismatch(format!(
+                "record of {} values for table {} with {} stored columns",
+                record.body.len(),
+                table,
+                stored
+            )));
+        }
+        let root_page = self.table_root_page(table)?;
+        let payload = record.to_bytes(self.header().text_encoding, self.header().schema_format);
+        btree::insert(&mut self.pager, root_page, row_id, &payload)
+    }
+
+    pub fn find_by_primary_key(&mut self, table: &str, key: i64) -> Result<Option<TableBTreeLeafCell>> {
+        self.refresh()?;
+        let root_page = self.table_root_page(table)?;
+        find_by_primary_key(&mut self.pager, root_page, key)
+    }
+
+    pub fn find_by_primary_key_list(&mut self, table: &str, key_list: &[i64]) -> Result<Option<Vec<TableBTreeLeafCell>>> {
+        self.refresh()?;
+        let root_page = self.table_root_page(table)?;
+        find_by_primary_key_list(&mut self.pager, root_page, key_list)
+    }
+
+    /// `key` holds values for the leading index columns, any number of them
+    pub fn find_keys_in_index(&mut self, index: &str, key: &[Column]) -> Result<Option<Vec<i64>>> {
+        self.refresh()?;
+        let root_page = self.index_root_page(index)?;
+        let key_order = self.index_key_order(index)?;
+        find_keys_in_index(&mut self.pager, root_page, key, &key_order)
+    }
+
+    pub fn find_key_in_index(&mut self, index: &str, key: &[Column]) -> Result<Option<i64>> {
+        self.refresh()?;
+        let root_page = self.index_root_page(index)?;
+        let key_order = self.index_key_order(index)?;
+        find_key_in_index(&mut self.pager, root_page, key, &key_order)
+    }
+}
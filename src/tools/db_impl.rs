@@ -1,235 +1,538 @@
-use crate::tools::{defs::*, helper::read_u16};
-use std::{fs::File, io::Seek};
-
-pub fn parse_header(f: &mut File) {
-    let mut buf = [0u8; HEADER_SIZE];
-    std::io::Read::read(f, &mut buf).unwrap();
-    SqliteHeader::new(&buf);
-}
-
-pub fn read_first_page(f: &mut File) {
-    let mut page = [0u8; PAGE_SIZE - HEADER_SIZE];
-    f.seek(std::io::SeekFrom::Start(HEADER_SIZE as u64))
-        .expect("seek failed");
-    std::io::Read::read(f, &mut page).unwrap();
-    let h = BTreePageLeafHeader::new(&page[0..8]);
-
-    eprintln!("{:x?}", page);
-    println!("cell count {}", h.cell_count);
-    // let mut cell_pointer_array:Vec<u16> = Vec::new();
-    for i in 0..h.cell_count {
-        let offset = read_u16(&page, &mut ((8 + i * 2) as usize)) - HEADER_SIZE as u16;
-        // cell_pointer_array.push(read_u16(&page, (8 + i * 2) as usize ));
-        let cell = TableBTreeLeafCell::new(&page, offset as usize);
-        let record = Record::new(&cell.payload);
-        record.print();
-        // eprintln!("{:?}", record);
+use crate::tools::{
+    cursor::MAX_DEPTH,
+    defs::*,
+    error::{Error, Result},
+    helper::read_u32,
+    pager::Pager,
+    ptrmap::{is_pointer_map_page, read_pointer_map_page},
+    schema::{Schema, SchemaEntry},
+    view::{PageKind, PageRef, RecordView, ValueRef},
+};
+use std::{
+    borrow::Cow,
+    cmp::{self, Ordering},
+};
+
+/// parses the sqlite_schema table rooted at page 1
+pub fn read_first_page(pager: &mut Pager) -> Result<Schema> {
+    let mut cells: Vec<TableBTreeLeafCell> = vec![];
+    read_table(pager, 1, &mut cells)?;
+    let encoding = pager.header().text_encoding;
+    let mut entries: Vec<SchemaEntry> = vec![];
+    for cell in cells {
+        let record = Record::new(&cell.payload, encoding).map_err(|e| e.on_page(1))?;
+        entries.push(SchemaEntry::new(&record).map_err(|e| e.on_page(1))?);
     }
+    Ok(Schema::new(entries))
 }
 
-pub fn read_page(f: &mut File, page: usize) -> Page {
-	if page == 0 {
-		panic!("read_page offset == 0");
-	}
-	let offset = page - 1;
-    let mut page = [0u8; PAGE_SIZE];
-    f.seek(std::io::SeekFrom::Start((PAGE_SIZE * offset) as u64))
-        .expect("seek failed");
-    std::io::Read::read(f, &mut page).unwrap();
-    let serial_type = page[0];
+pub fn read_page(pager: &mut Pager, page: usize) -> Result<Page> {
+    let usable_size = pager.header().usable_size();
+    // page 1 starts with the database header, the b-tree header follows it
+    let header_offset = if page == 1 { HEADER_SIZE } else { 0 };
+    let page_number = page as u32;
+    // pointer map pages have no b-tree header, they are only known by their position
+    if is_pointer_map_page(pager.header(), page_number) {
+        return read_pointer_map_page(pager, page_number).map(Page::PointerMapPage);
+    }
+    let page = pager.page_bytes(page)?;
+    let serial_type = page[header_offset];
     // eprintln!("serial type {}", serial_type);
 	
-    let parsed_page: Page = match serial_type {
-		0x02 => Page::IndexBTreeInteriorPage(IndexBTreeInteriorPage::new(&page)),
-        0x05 => Page::TableBTreeInteriorPage(TableBTreeInteriorPage::new(&page)),
-		0x0a => Page::IndexBTreeLeafPage(IndexBTreeLeafPage::new(&page)),
-        0x0d => Page::TableBTreeLeafPage(TableBTreeLeafPage::new(&page)),
-        _ => panic!("invalid page type"),
+    let parsed_page = match serial_type {
+		0x02 => IndexBTreeInteriorPage::new(&page, header_offset, usable_size).map(Page::IndexBTreeInteriorPage),
+        0x05 => TableBTreeInteriorPage::new(&page, header_offset, usable_size).map(Page::TableBTreeInteriorPage),
+		0x0a => IndexBTreeLeafPage::new(&page, header_offset, usable_size).map(Page::IndexBTreeLeafPage),
+        0x0d => TableBTreeLeafPage::new(&page, header_offset, usable_size).map(Page::TableBTreeLeafPage),
+        x => Err(Error::corrupt(format!("invalid page type {:#04x}", x))),
     };
+    drop(page);
+    let mut parsed_page = parsed_page.map_err(|e| e.on_page(page_number))?;
 
-	// if offset == 1  {
-	// 	if let Page::TableBTreeInteriorPage(p) = &parsed_page {
-	// 		for cell in &p.cells {
-	// 			eprintln!("{:?}", cell);	
-	// 		}
-	// 	}
-	// 	// eprintln!("{:x?}", page);
-		
-	// }
+    match &mut parsed_page {
+        Page::TableBTreeLeafPage(p) => {
+            for cell in &mut p.cells {
+                read_overflow(pager, &mut cell.payload, cell.payload_size, cell.overflow_page)?;
+            }
+        }
+        Page::IndexBTreeLeafPage(p) => {
+            for cell in &mut p.cells {
+                read_overflow(pager, &mut cell.payload, cell.payload_size, cell.overflow_page)?;
+            }
+        }
+        Page::IndexBTreeInteriorPage(p) => {
+            for cell in &mut p.cells {
+                read_overflow(pager, &mut cell.payload, cell.payload_size, cell.overflow_page)?;
+            }
+        }
+        Page::TableBTreeInteriorPage(_) | Page::PointerMapPage(_) => {}
+    }
 
-    parsed_page
+    Ok(parsed_page)
 }
 
-pub fn read_table(f: &mut File, root_page: u32, cells: &mut Vec<TableBTreeLeafCell>) {
-    let root = read_page(f, root_page as usize);
-    match root {
-        Page::TableBTreeLeafPage(p) => {
-            for cell in p.cells {
-                cells.push(cell);
+/// appends the overflow chain starting at `overflow_page` to the local part of a payload
+fn read_overflow(pager: &mut Pager, payload: &mut Vec<u8>, payload_size: u64, overflow_page: u32) -> Result<()> {
+    let usable_size = pager.header().usable_size();
+    let mut next = overflow_page;
+    while (payload.len() as u64) < payload_size {
+        if next == 0 {
+            return Err(Error::corrupt(format!(
+                "overflow chain ends after {} of {} payload bytes",
+                payload.len(),
+                payload_size
+            )));
+        }
+        let page = pager.page_bytes(next as usize)?;
+        next = read_u32(&page, &mut 0)?;
+        let remaining = payload_size as usize - payload.len();
+        // the reserved tail of an overflow page holds no content either
+        let end = cmp::min(usable_size, 4 + remaining);
+        payload.extend_from_slice(&page[4..end]);
+    }
+    Ok(())
+}
+
+/// runs `f` on a view of the b-tree page, borrowed from the page source without copying cells
+pub fn with_page<T, F>(pager: &mut Pager, page: u32, f: F) -> Result<T>
+where
+    F: FnOnce(PageRef<'_>) -> Result<T>,
+{
+    let usable_size = pager.header().usable_size();
+    let bytes = pager.page_bytes(page as usize)?;
+    let view = page_ref(&bytes, page, usable_size)?;
+    f(view).map_err(|e| e.on_page(page))
+}
+
+/// calls `f` with the rowid and complete payload of every row in rowid order.
+/// Payloads are borrowed from the page, only rows with overflow pages are copied.
+pub fn scan_table<F>(pager: &mut Pager, root_page: u32, f: &mut F) -> Result<()>
+where
+    F: FnMut(i64, &[u8]) -> Result<()>,
+{
+    scan_table_impl(pager, root_page, f, 0)
+}
+
+fn scan_table_impl<F>(pager: &mut Pager, root_page: u32, f: &mut F, depth: usize) -> Result<()>
+where
+    F: FnMut(i64, &[u8]) -> Result<()>,
+{
+    let usable_size = pager.header().usable_size();
+    let bytes = pager.page_bytes(root_page as usize)?;
+    let view = page_ref(&bytes, root_page, usable_size)?;
+    match view.kind() {
+        PageKind::TableInterior => {
+            let mut children = view
+                .cells()
+                .map(|cell| cell.map(|c| c.left_child_pointer.unwrap_or(0)))
+                .collect::<Result<Vec<u32>>>()
+                .map_err(|e| e.on_page(root_page))?;
+            children.extend(view.right_most_pointer());
+            drop(bytes);
+            let depth = descend(root_page, depth)?;
+            for child in children {
+                scan_table_impl(pager, child, f, depth)?;
             }
         }
-        Page::TableBTreeInteriorPage(p) => {
-            println!("interior page {}", root_page);
-            for cell in p.cells {
-                read_table(f, cell.left_child_pointer, cells);
+        PageKind::TableLeaf => {
+            let spills = view.cells().any(|cell| cell.is_ok_and(|c| !c.is_complete()));
+            if !spills {
+                for cell in view.cells() {
+                    let cell = cell.map_err(|e| e.on_page(root_page))?;
+                    f(cell.row_id.unwrap_or(0), cell.payload)?;
+                }
+                return Ok(());
+            }
+            // overflow pages are read through the pager, so the page can not stay borrowed from it
+            let page = bytes.into_owned();
+            let view = page_ref(&page, root_page, usable_size)?;
+            for cell in view.cells() {
+                let cell = cell.map_err(|e| e.on_page(root_page))?;
+                let mut payload = cell.payload.to_vec();
+                read_overflow(pager, &mut payload, cell.payload_size, cell.overflow_page)?;
+                f(cell.row_id.unwrap_or(0), &payload)?;
             }
-            read_table(f, p.header.right_most_pointer, cells);
         }
-		_ => { panic!("expected table page, found index page"); }
+        _ => return Err(expected_table_page(root_page)),
     }
+    Ok(())
 }
 
-pub fn find_by_primary_key(f: &mut File, root_page: u32, key: u64) -> Option<TableBTreeLeafCell>{
-	let root = read_page(f, root_page as usize);
+pub fn read_table(pager: &mut Pager, root_page: u32, cells: &mut Vec<TableBTreeLeafCell>) -> Result<()> {
+    read_table_impl(pager, root_page, cells, 0)
+}
+
+fn read_table_impl(pager: &mut Pager, root_page: u32, cells: &mut Vec<TableBTreeLeafCell>, depth: usize) -> Result<()> {
+    let root = read_page(pager, root_page as usize)?;
     match root {
         Page::TableBTreeLeafPage(p) => {
             for cell in p.cells {
-                if cell.row_id == key {
-					return Some(cell)
-				} 
+                cells.push(cell);
             }
-			None
         }
         Page::TableBTreeInteriorPage(p) => {
-            println!("interior page {}", root_page);
+            let depth = descend(root_page, depth)?;
             for cell in p.cells {
-				if key <= cell.row_id {
-					return find_by_primary_key(f, cell.left_child_pointer, key);
-				}
+                read_table_impl(pager, cell.left_child_pointer, cells, depth)?;
             }
-            return find_by_primary_key(f, p.header.right_most_pointer, key);
+            read_table_impl(pager, p.header.right_most_pointer, cells, depth)?;
         }
-		_ => { panic!("expected table page, found index page"); }
+		_ => return Err(expected_table_page(root_page)),
     }
+    Ok(())
 }
 
-pub fn find_keys_in_index(f: &mut File, root_page: u32, key: Column) -> Option<Vec<u64>>{
-	let mut result: Vec<u64> = vec![];
-	find_key_in_index_impl(f, root_page, &key, &mut result, false);
-	if result.len() > 0 {
-		Some(result)
-	} else {
-		None
+pub fn find_by_primary_key(pager: &mut Pager, root_page: u32, key: i64) -> Result<Option<TableBTreeLeafCell>>{
+	find_by_primary_key_impl(pager, root_page, key, 0)
+}
+
+fn find_by_primary_key_impl(pager: &mut Pager, root_page: u32, key: i64, depth: usize) -> Result<Option<TableBTreeLeafCell>>{
+	let usable_size = pager.header().usable_size();
+	let bytes = pager.page_bytes(root_page as usize)?;
+	let view = page_ref(&bytes, root_page, usable_size)?;
+	if !view.kind().is_table() {
+		return Err(expected_table_page(root_page));
 	}
+	let i = lower_bound(view.cell_count(), |i| Ok(table_row_id(&view, i)?.cmp(&key))).map_err(|e| e.on_page(root_page))?;
+	if view.kind() == PageKind::TableInterior {
+		let child = child_at(&view, i).map_err(|e| e.on_page(root_page))?;
+		drop(bytes);
+		return find_by_primary_key_impl(pager, child, key, descend(root_page, depth)?);
+	}
+	if i == view.cell_count() || table_row_id(&view, i)? != key {
+		return Ok(None);
+	}
+	let mut cell = view.cell(i)?.to_table_leaf_cell();
+	drop(bytes);
+	read_overflow(pager, &mut cell.payload, cell.payload_size, cell.overflow_page)?;
+	Ok(Some(cell))
 }
 
-pub fn find_key_in_index(f: &mut File, root_page: u32, key: Column) -> Option<u64>{
-	let mut result: Vec<u64> = vec![];
-	find_key_in_index_impl(f, root_page, &key, &mut result, true);
-	if result.len() > 0 {
-		Some(result[0])
+/// rowids of all entries whose leading columns equal `key`, which may be any prefix of the index columns
+pub fn find_keys_in_index(pager: &mut Pager, root_page: u32, key: &[Column], key_order: &KeyOrder) -> Result<Option<Vec<i64>>>{
+	let mut result: Vec<i64> = vec![];
+	find_key_in_index_impl(pager, root_page, key, key_order, &mut result, false, 0)?;
+	if !result.is_empty() {
+		Ok(Some(result))
 	} else {
-		None
+		Ok(None)
 	}
 }
 
-fn find_key_in_index_impl(f: &mut File, root_page: u32, key: &Column, result:&mut Vec<u64>, distinct: bool){
-	let root = read_page(f, root_page as usize);
-	match root {
-        Page::IndexBTreeLeafPage(p) => {
-            for cell in p.cells {
-				let index_record = Record::new(&cell.payload);
-                let first = index_record.body.first().unwrap();
-				let last = index_record.body.last().unwrap();
-				if *first == *key {
-					// println!("found key in leaf, page {} row_id {:?}, record len= {}", root_page, *last, index_record.body.len());
-					result.push(force_cast_column_to_u64(last));
-					if distinct {
-						return;
-					}
-				}
-            }
-        }
-        Page::IndexBTreeInteriorPage(p) => {
-            // println!("interior page {}", root_page);
-            for cell in p.cells {
-				let index_record = Record::new(&cell.payload);
-				let first = index_record.body.first().unwrap();
-				let last = index_record.body.last().unwrap();
-				if *key < *first {
-					println!("deeper into tree {:?}", *first);
-					return find_key_in_index_impl(f, cell.left_child_pointer, key, result, distinct);
-				}
-				if *key == *first {
-					println!("found key in interior, page {} row_id {:?}", root_page, *last);
-					result.push(force_cast_column_to_u64(last));
-					if distinct {
-						return;
-					}
-					find_key_in_index_impl(f, cell.left_child_pointer, key, result, distinct);
-				}
-            }
-            return find_key_in_index_impl(f, p.header.right_most_pointer, key, result, distinct);
+pub fn find_key_in_index(pager: &mut Pager, root_page: u32, key: &[Column], key_order: &KeyOrder) -> Result<Option<i64>>{
+	let mut result: Vec<i64> = vec![];
+	find_key_in_index_impl(pager, root_page, key, key_order, &mut result, true, 0)?;
+	Ok(result.first().copied())
+}
+
+fn find_key_in_index_impl(pager: &mut Pager, root_page: u32, key: &[Column], key_order: &KeyOrder, result:&mut Vec<i64>, distinct: bool, depth: usize) -> Result<()>{
+	let probe = probe_index_page(pager, root_page, key, key_order, distinct)?;
+	for (child, row_id) in probe.matches {
+		if let Some(child) = child {
+			// matches may continue in the left subtree
+			find_key_in_index_impl(pager, child, key, key_order, result, distinct, descend(root_page, depth)?)?;
+			if distinct && !result.is_empty() {
+				return Ok(());
+			}
+		}
+		result.push(row_id);
+		if distinct {
+			return Ok(());
+		}
+	}
+	match probe.next_child {
+		Some(child) => find_key_in_index_impl(pager, child, key, key_order, result, distinct, descend(root_page, depth)?),
+		None => Ok(()),
+	}
+}
+
+/// what one index page contributes to a lookup, gathered while the page is borrowed
+struct IndexProbe {
+	/// rowids of the entries equal to the key, each with the child left of it on interior pages
+	matches: Vec<(Option<u32>, i64)>,
+	/// subtree after the last match that may hold more of them
+	next_child: Option<u32>,
+}
+
+fn probe_index_page(pager: &mut Pager, page: u32, key: &[Column], key_order: &KeyOrder, distinct: bool) -> Result<IndexProbe> {
+	let usable_size = pager.header().usable_size();
+	let bytes = pager.page_bytes(page as usize)?;
+	let view = page_ref(&bytes, page, usable_size)?;
+	if view.kind().is_table() {
+		return Err(Error::Corrupt {
+			page,
+			reason: "expected index page, found table page".into(),
+		});
+	}
+	if let Some(probe) = search_index_page(&view, None, key, key_order, distinct).map_err(|e| e.on_page(page))? {
+		return Ok(probe);
+	}
+	// a probed entry continues on overflow pages, they are read through the pager
+	// so the page must not stay borrowed from it
+	let bytes = bytes.into_owned();
+	let view = page_ref(&bytes, page, usable_size)?;
+	search_index_page(&view, Some(pager), key, key_order, distinct)
+		.map_err(|e| e.on_page(page))
+		.map(|probe| probe.expect("overflow is read when a pager is given"))
+}
+
+/// binary searches the page for the first entry not before `key`, then collects the run of
+/// equal entries. None when an entry spills to overflow pages and there is no pager to read them.
+fn search_index_page(view: &PageRef, mut pager: Option<&mut Pager>, key: &[Column], key_order: &KeyOrder, distinct: bool) -> Result<Option<IndexProbe>> {
+	let count = view.cell_count();
+	let mut spilled = false;
+	let start = lower_bound(count, |i| match index_payload(view, i, &mut pager)? {
+		Some(payload) => compare_index_entry(&RecordView::new(&payload, key_order.encoding)?, key, key_order),
+		None => {
+			spilled = true;
+			Ok(Ordering::Equal)
+		}
+	})?;
+	if spilled {
+		return Ok(None);
+	}
+
+	let mut matches = vec![];
+	let mut i = start;
+	let next_child = loop {
+		if i == count {
+			break view.right_most_pointer();
+		}
+		let left_child_pointer = view.cell(i)?.left_child_pointer;
+		let payload = match index_payload(view, i, &mut pager)? {
+			Some(p) => p,
+			None => return Ok(None),
+		};
+		let record = RecordView::new(&payload, key_order.encoding)?;
+		if compare_index_entry(&record, key, key_order)? != Ordering::Equal {
+			// everything else matching sits left of this entry
+			break left_child_pointer;
+		}
+		matches.push((left_child_pointer, index_row_id(&record)?));
+		if distinct {
+			break None;
+		}
+		i += 1;
+	};
+	Ok(Some(IndexProbe { matches, next_child }))
+}
+
+/// complete payload of the `index`th cell, None when it needs overflow pages and `pager` is None
+fn index_payload<'a>(view: &PageRef<'a>, index: usize, pager: &mut Option<&mut Pager>) -> Result<Option<Cow<'a, [u8]>>> {
+	let cell = view.cell(index)?;
+	if cell.is_complete() {
+		return Ok(Some(Cow::Borrowed(cell.payload)));
+	}
+	match pager {
+		Some(pager) => {
+			let mut payload = cell.payload.to_vec();
+			read_overflow(pager, &mut payload, cell.payload_size, cell.overflow_page)?;
+			Ok(Some(Cow::Owned(payload)))
+		}
+		None => Ok(None),
+	}
+}
+
+/// decodes only as many leading columns as the key has
+fn compare_index_entry(record: &RecordView, key: &[Column], key_order: &KeyOrder) -> Result<Ordering> {
+	let entry = (0..key.len().min(record.len()))
+		.map(|i| record.get(i).map(|v| v.map_or(Column::NULL, |v| v.to_column())))
+		.collect::<Result<Vec<Column>>>()?;
+	Ok(key_order.compare_prefix(&entry, key))
+}
+
+/// the rowid is stored as the last column of an index entry
+fn index_row_id(record: &RecordView) -> Result<i64> {
+	let last = match record.len().checked_sub(1) {
+		Some(i) => record.get(i)?,
+		None => return Err(Error::corrupt("empty index record")),
+	};
+	match last {
+		Some(ValueRef::Integer(v)) => Ok(v),
+		v => Err(Error::TypeMismatch(format!("expected integer row id, found {:?}", v))),
+	}
+}
+
+/// depth of the children of `page`, a tree deeper than sqlite allows has a cycle
+fn descend(page: u32, depth: usize) -> Result<usize> {
+    if depth >= MAX_DEPTH {
+        return Err(Error::Corrupt {
+            page,
+            reason: "b-tree too deep".into(),
+        });
+    }
+    Ok(depth + 1)
+}
+
+pub(crate) fn expected_table_page(page: u32) -> Error {
+    Error::Corrupt {
+        page,
+        reason: "expected table page, found index page".into(),
+    }
+}
+
+/// first position in `0..count` whose entry does not order before the
+/// search key, `compare(i)` orders the `i`th entry against the key
+fn lower_bound<F>(count: usize, mut compare: F) -> Result<usize>
+where
+    F: FnMut(usize) -> Result<Ordering>,
+{
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if compare(mid)? == Ordering::Less {
+            low = mid + 1;
+        } else {
+            high = mid;
         }
-		_ => { panic!("expected index page, found table page"); }
     }
+    Ok(low)
 }
 
-fn force_cast_column_to_u64(c: &Column) -> u64{
-	match *c {
-		Column::I64(v)	=> v as u64,
-		Column::True	=> 1,
-		Column::False	=> 0,
-		_ => panic!("not an int"),
-	}
+fn page_ref(bytes: &[u8], page: u32, usable_size: usize) -> Result<PageRef<'_>> {
+    // page 1 starts with the database header, the b-tree header follows it
+    let header_offset = if page == 1 { HEADER_SIZE } else { 0 };
+    PageRef::new(bytes, header_offset, usable_size).map_err(|e| e.on_page(page))
+}
+
+fn table_row_id(view: &PageRef, index: usize) -> Result<i64> {
+    view.cell(index)?
+        .row_id
+        .ok_or_else(|| Error::corrupt("table cell without rowid"))
+}
+
+/// child left of the `index`th cell, the right-most child past the last cell
+fn child_at(view: &PageRef, index: usize) -> Result<u32> {
+    let child = if index < view.cell_count() {
+        view.cell(index)?.left_child_pointer
+    } else {
+        view.right_most_pointer()
+    };
+    child.ok_or_else(|| Error::corrupt("leaf page has no children"))
 }
 
-pub fn find_by_primary_key_list(f: &mut File, root_page: u32, key_list: &Vec<u64>) -> Option<Vec<TableBTreeLeafCell>>{
-	if key_list.len() == 0 {
-		return None;
+pub fn find_by_primary_key_list(pager: &mut Pager, root_page: u32, key_list: &[i64]) -> Result<Option<Vec<TableBTreeLeafCell>>>{
+	if key_list.is_empty() {
+		return Ok(None);
 	}
 	let mut results: Vec<TableBTreeLeafCell> = vec![];
-	let mut key_list_sorted = key_list.clone();
-	let mut current_index = 0;
+	let mut key_list_sorted = key_list.to_vec();
 	key_list_sorted.sort();
-	_find_by_primary_key_list(f, root_page, &key_list_sorted, &mut current_index, &mut results);
+	key_list_sorted.dedup();
+	_find_by_primary_key_list(pager, root_page, &key_list_sorted, &mut results, 0)?;
 
-	if results.len() == 0 {
-		None
+	if results.is_empty() {
+		Ok(None)
 	} else {
-		Some(results)
+		Ok(Some(results))
 	}
 }
 
-fn _find_by_primary_key_list(f: &mut File, root_page: u32, key_list_sorted: &Vec<u64>, current_index: &mut usize, results: &mut Vec<TableBTreeLeafCell>){
-	let root = read_page(f, root_page as usize);
-    match root {
-        Page::TableBTreeLeafPage(p) => {
-			let mut current_key = key_list_sorted[*current_index];
-            for cell in p.cells {
-				if cell.row_id < current_key {
-					continue;
+/// `key_list_sorted` holds the keys that fall into the subtree rooted at `root_page`
+fn _find_by_primary_key_list(pager: &mut Pager, root_page: u32, key_list_sorted: &[i64], results: &mut Vec<TableBTreeLeafCell>, depth: usize) -> Result<()>{
+	let usable_size = pager.header().usable_size();
+	let bytes = pager.page_bytes(root_page as usize)?;
+	let view = page_ref(&bytes, root_page, usable_size)?;
+	let count = view.cell_count();
+	// each key is searched from where the previous one was found
+	let mut start = 0;
+	let seek = |start: usize, key: i64| -> Result<usize> {
+		let i = lower_bound(count - start, |i| Ok(table_row_id(&view, start + i)?.cmp(&key)))?;
+		Ok(start + i)
+	};
+	match view.kind() {
+		PageKind::TableLeaf => {
+			let mut found = vec![];
+			for &key in key_list_sorted {
+				start = seek(start, key).map_err(|e| e.on_page(root_page))?;
+				if start < count && table_row_id(&view, start)? == key {
+					found.push(view.cell(start)?.to_table_leaf_cell());
 				}
-                if cell.row_id == current_key {
-					results.push(cell);
-				}
-				*current_index += 1;
-				if key_list_sorted.len() <= *current_index {
-					return;
-				}
-				current_key = key_list_sorted[*current_index];
-            }
-        }
-        Page::TableBTreeInteriorPage(p) => {
-            println!("interior page {}", root_page);
-			let mut current_key = key_list_sorted[*current_index];
-            for cell in p.cells {
-				if current_key <= cell.row_id {
-					_find_by_primary_key_list(f, cell.left_child_pointer, key_list_sorted, current_index, results);
-					// return find_by_primary_key(f, cell.left_child_pointer, key);
-					if key_list_sorted.len() <= *current_index {
-						return;
+			}
+			drop(bytes);
+			for mut cell in found {
+				read_overflow(pager, &mut cell.payload, cell.payload_size, cell.overflow_page)?;
+				results.push(cell);
+			}
+		}
+		PageKind::TableInterior => {
+			let mut children = vec![];
+			let mut rest = key_list_sorted;
+			while let Some(&first) = rest.first() {
+				start = seek(start, first).map_err(|e| e.on_page(root_page))?;
+				// keys up to the cell's rowid belong to the child left of it
+				let n = match start < count {
+					true => {
+						let bound = table_row_id(&view, start)?;
+						rest.partition_point(|&k| k <= bound)
 					}
-					current_key = key_list_sorted[*current_index];
-				}
-            }
-			_find_by_primary_key_list(f, p.header.right_most_pointer, key_list_sorted, current_index, results);
-            // find_by_primary_key(f, p.header.right_most_pointer, key);
-        }
-		_ => { panic!("expected table page, found index page"); }
-    }
+					false => rest.len(),
+				};
+				children.push((child_at(&view, start)?, &rest[..n]));
+				rest = &rest[n..];
+				start += 1;
+			}
+			drop(bytes);
+			let depth = descend(root_page, depth)?;
+			for (child, keys) in children {
+				_find_by_primary_key_list(pager, child, keys, results, depth)?;
+			}
+		}
+		_ => return Err(expected_table_page(root_page)),
+	}
+	Ok(())
 }
 
-// fn table_find_rows_with_key_set(f: &mut File, root_page: u32, key: &Column, result:&mut Vec<u64>){
+// fn table_find_rows_with_key_set(pager: &mut Pager, root_page: u32, key: &Column, result:&mut Vec<u64>){
 // 	Option<TableBTreeLeafCell>
-// }
\ No newline at end of file
+// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::{
+        database::Database,
+        ddl::SortOrder,
+        helper::{write_u16, write_u32},
+        pager::DatabaseOptions,
+        testing::TempDb,
+    };
+    use std::fs;
+
+    fn is_too_deep<T: std::fmt::Debug>(result: Result<T>) -> bool {
+        matches!(result, Err(Error::Corrupt { ref reason, .. }) if reason.contains("too deep"))
+    }
+
+    #[test]
+    fn page_pointing_to_itself_is_corrupt() {
+        let temp = TempDb::create(
+            "db-impl-cycle",
+            512,
+            &[("t", "CREATE TABLE t(a)"), ("u", "CREATE TABLE u(a)")],
+        );
+        // page 2 becomes a table interior page and page 3 an index interior page,
+        // both without cells and with themselves as right-most child
+        let mut data = fs::read(&temp.path).unwrap();
+        for (page, flag) in [(2u32, 0x05), (3, 0x02)] {
+            let offset = (page as usize - 1) * 512;
+            data[offset] = flag;
+            write_u16(&mut data, offset + 3, 0);
+            write_u16(&mut data, offset + 5, 512);
+            write_u32(&mut data, offset + 8, page);
+        }
+        fs::write(&temp.path, data).unwrap();
+
+        let mut db = Database::open_with_options(&temp.path, &DatabaseOptions::default()).unwrap();
+        assert!(is_too_deep(db.find_by_primary_key("t", 1)));
+        assert!(is_too_deep(db.read_table("t")));
+        assert!(is_too_deep(db.scan_table("t", |_, _| Ok(()))));
+        assert!(is_too_deep(find_by_primary_key_list(db.pager(), 2, &[1, 2, 3])));
+
+        let key_order = KeyOrder {
+            columns: vec![(SortOrder::Asc, Collation::Binary)],
+            encoding: TextEncoding::Utf8,
+        };
+        let key = [Column::I64(1)];
+        assert!(is_too_deep(find_keys_in_index(db.pager(), 3, &key, &key_order)));
+        assert!(is_too_deep(find_key_in_index(db.pager(), 3, &key, &key_order)));
+    }
+}
@@ -0,0 +1,1056 @@
+use crate::tools::{
+    defs::*,
+    error::{Error, Result},
+    view::RecordView,
+};
+
+//######################################################
+// definitions
+//######################################################
+
+/// column type affinity as derived from the declared type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Affinity {
+    Text,
+    Numeric,
+    Integer,
+    Real,
+    Blob,
+}
+
+impl Affinity {
+    /// sqlite's five rules, applied in order
+    pub fn from_declared_type(declared_type: Option<&str>) -> Affinity {
+        let t = match declared_type {
+            Some(t) => t.to_ascii_uppercase(),
+            None => return Affinity::Blob,
+        };
+        if t.contains("INT") {
+            Affinity::Integer
+        } else if t.contains("CHAR") || t.contains("CLOB") || t.contains("TEXT") {
+            Affinity::Text
+        } else if t.contains("BLOB") || t.is_empty() {
+            Affinity::Blob
+        } else if t.contains("REAL") || t.contains("FLOA") || t.contains("DOUB") {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Generated {
+    Stored,
+    Virtual,
+}
+
+#[derive(Debug, Clone)]
+pub struct ColumnDef {
+    pub name: String,
+    pub declared_type: Option<String>,
+    pub affinity: Affinity,
+    pub primary_key: bool,
+    pub not_null: bool,
+    /// default expression as written in the schema
+    pub default: Option<String>,
+    pub collation: Option<String>,
+    pub unique: bool,
+    pub generated: Option<Generated>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TableDef {
+    pub name: String,
+    pub columns: Vec<ColumnDef>,
+    pub primary_key: Vec<String>,
+    pub unique: Vec<Vec<String>>,
+    /// PRIMARY KEY and UNIQUE constraints in declaration order
+    pub key_constraints: Vec<KeyConstraint>,
+    pub without_rowid: bool,
+    pub strict: bool,
+    /// index of the INTEGER PRIMARY KEY column that aliases the rowid
+    pub rowid_alias: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexedColumn {
+    /// set when the indexed term is a plain column reference
+    pub name: Option<String>,
+    pub expr: String,
+    pub collation: Option<String>,
+    pub order: SortOrder,
+}
+
+/// a PRIMARY KEY or UNIQUE constraint, sqlite backs it with an automatic index
+#[derive(Debug, Clone)]
+pub struct KeyConstraint {
+    pub primary_key: bool,
+    pub columns: Vec<IndexedColumn>,
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexDef {
+    pub name: String,
+    pub table: String,
+    pub unique: bool,
+    pub columns: Vec<IndexedColumn>,
+    /// condition of a partial index
+    pub where_clause: Option<String>,
+}
+
+impl TableDef {
+    pub fn column(&self, name: &str) -> Option<&ColumnDef> {
+        self.columns.iter().find(|c| c.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c.name.eq_ignore_ascii_case(name))
+    }
+
+    /// definition of the automatic index `sqlite_autoindex_<table>_<n>`. sqlite numbers the
+    /// PRIMARY KEY and UNIQUE constraints in declaration order, leaving out the rowid alias
+    /// and constraints that repeat the columns of an earlier one
+    pub fn automatic_index(&self, name: &str) -> Option<IndexDef> {
+        let (_, n) = name.strip_prefix("sqlite_autoindex_")?.rsplit_once('_')?;
+        let n: usize = n.parse().ok()?;
+        let mut indexed: Vec<&KeyConstraint> = vec![];
+        for constraint in &self.key_constraints {
+            if constraint.primary_key && self.rowid_alias.is_some() {
+                continue;
+            }
+            if indexed.iter().any(|k| self.same_key(k, constraint)) {
+                continue;
+            }
+            indexed.push(constraint);
+        }
+        let constraint = indexed.get(n.checked_sub(1)?)?;
+        Some(IndexDef {
+            name: name.to_string(),
+            table: self.name.clone(),
+            unique: true,
+            columns: constraint.columns.clone(),
+            where_clause: None,
+        })
+    }
+
+    fn same_key(&self, a: &KeyConstraint, b: &KeyConstraint) -> bool {
+        a.columns.len() == b.columns.len()
+            && a.columns.iter().zip(&b.columns).all(|(x, y)| x.identity(self) == y.identity(self))
+    }
+
+    /// maps a table b-tree record onto the declared columns
+    pub fn row(&self, row_id: i64, record: Record) -> Row {
+        let mut values = record.body.into_iter();
+        let mut fields: Vec<(String, Column)> = vec![];
+        for (i, column) in self.columns.iter().enumerate() {
+            // virtual generated columns are computed on read and never stored
+            if column.generated == Some(Generated::Virtual) {
+                fields.push((column.name.clone(), Column::NULL));
+                continue;
+            }
+            fields.push((column.name.clone(), self.field_value(i, row_id, values.next())));
+        }
+        Row { row_id, fields }
+    }
+
+    /// like `row` but only decodes the columns at the given positions of the definition
+    pub fn project(&self, row_id: i64, record: &RecordView, columns: &[usize]) -> Result<Row> {
+        let mut fields: Vec<(String, Column)> = vec![];
+        for &i in columns {
+            let column = self
+                .columns
+                .get(i)
+                .ok_or_else(|| Error::NotFound(format!("column {} of table {}", i, self.name)))?;
+            if column.generated == Some(Generated::Virtual) {
+                fields.push((column.name.clone(), Column::NULL));
+                continue;
+            }
+            let stored = self.columns[..i]
+                .iter()
+                .filter(|c| c.generated != Some(Generated::Virtual))
+                .count();
+            let value = record.get(stored)?.map(|v| v.to_column());
+            fields.push((column.name.clone(), self.field_value(i, row_id, value)));
+        }
+        Ok(Row { row_id, fields })
+    }
+
+    /// value of the `i`th column given what the record stores for it
+    fn field_value(&self, i: usize, row_id: i64, stored: Option<Column>) -> Column {
+        match stored {
+            Some(_) if self.rowid_alias == Some(i) => Column::I64(row_id),
+            // REAL columns store integral values as integers to save space
+            Some(Column::I64(v)) if self.columns[i].affinity == Affinity::Real => Column::F64(v as f64),
+            Some(v) => v,
+            // rows written before an ALTER TABLE ADD COLUMN are shorter than the table
+            None => match &self.columns[i].default {
+                Some(d) => literal_value(d),
+                None => Column::NULL,
+            },
+        }
+    }
+}
+
+impl IndexedColumn {
+    fn named(name: &str, order: SortOrder) -> IndexedColumn {
+        IndexedColumn {
+            name: Some(name.to_string()),
+            expr: name.to_string(),
+            collation: None,
+            order,
+        }
+    }
+
+    /// the collation given here, else the one declared on the table column
+    fn collation_in<'t>(&'t self, table: &'t TableDef) -> Option<&'t str> {
+        self.collation.as_deref().or_else(|| {
+            self.name
+                .as_ref()
+                .and_then(|n| table.column(n))
+                .and_then(|t| t.collation.as_deref())
+        })
+    }
+
+    /// what sqlite compares to tell whether two index columns are the same
+    fn identity(&self, table: &TableDef) -> (String, String) {
+        (
+            self.name.as_deref().unwrap_or(&self.expr).to_ascii_lowercase(),
+            self.collation_in(table).unwrap_or("BINARY").to_ascii_uppercase(),
+        )
+    }
+}
+
+impl IndexDef {
+    /// collation of each key column comes from the index, else from the table column.
+    /// Entries end with the rowid, or in a WITHOUT ROWID table with the primary key
+    /// columns the index does not already hold
+    pub fn key_order(&self, table: &TableDef, encoding: TextEncoding) -> Result<KeyOrder> {
+        let key_column = |c: &IndexedColumn| -> Result<(SortOrder, Collation)> {
+            let collation = match c.collation_in(table) {
+                Some(name) => Collation::from_name(name)?,
+                None => Collation::Binary,
+            };
+            Ok((c.order, collation))
+        };
+        let mut columns = self.columns.iter().map(key_column).collect::<Result<Vec<_>>>()?;
+        if !table.without_rowid {
+            columns.push((SortOrder::Asc, Collation::Binary));
+        } else if let Some(primary_key) = table.key_constraints.iter().find(|k| k.primary_key) {
+            for c in &primary_key.columns {
+                if !self.columns.iter().any(|i| i.identity(table) == c.identity(table)) {
+                    columns.push(key_column(c)?);
+                }
+            }
+        }
+        Ok(KeyOrder { columns, encoding })
+    }
+}
+
+//######################################################
+// row
+//######################################################
+
+#[derive(Debug)]
+pub struct Row {
+    pub row_id: i64,
+    pub fields: Vec<(String, Column)>,
+}
+
+impl Row {
+    pub fn get(&self, name: &str) -> Option<&Column> {
+        self.fields
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v)
+    }
+}
+
+/// evaluates a DEFAULT clause if it is a plain literal, anything else reads as NULL
+fn literal_value(sql: &str) -> Column {
+    let tokens = match tokenize(sql) {
+        Ok(t) => t,
+        Err(_) => return Column::NULL,
+    };
+    let (negative, token) = match tokens.as_slice() {
+        [(Token::Symbol('-'), _, _), (t, _, _)] => (true, t),
+        [(Token::Symbol('+'), _, _), (t, _, _)] => (false, t),
+        [(t, _, _)] => (false, t),
+        _ => return Column::NULL,
+    };
+    match token {
+        Token::Number(n) => {
+            let n = if negative { format!("-{}", n) } else { n.clone() };
+            if let Ok(v) = n.parse::<i64>() {
+                Column::I64(v)
+            } else if let Ok(v) = n.parse::<f64>() {
+                Column::F64(v)
+            } else {
+                Column::NULL
+            }
+        }
+        Token::Str(s) if !negative => Column::Text(Text::new(s)),
+        Token::Blob(b) if !negative => Column::Blob(Blob {
+            size: b.len() as u64,
+            data: b.clone(),
+        }),
+        Token::Word(w) if w.eq_ignore_ascii_case("true") => Column::I64(1),
+        Token::Word(w) if w.eq_ignore_ascii_case("false") => Column::I64(0),
+        _ => Column::NULL,
+    }
+}
+
+//######################################################
+// tokenizer
+//######################################################
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// bare word, keywords are words too
+    Word(String),
+    QuotedIdent(String),
+    Str(String),
+    Blob(Vec<u8>),
+    Number(String),
+    Symbol(char),
+}
+
+fn malformed<S: AsRef<str>>(reason: S) -> Error {
+    Error::corrupt(format!("malformed schema sql: {}", reason.as_ref()))
+}
+
+/// tokens with their byte range in the source text
+fn tokenize(sql: &str) -> Result<Vec<(Token, usize, usize)>> {
+    let bytes = sql.as_bytes();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+        if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c == b'-' && bytes.get(i + 1) == Some(&b'-') {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+            continue;
+        }
+        if c == b'/' && bytes.get(i + 1) == Some(&b'*') {
+            i += 2;
+            while i < bytes.len() && !(bytes[i] == b'*' && bytes.get(i + 1) == Some(&b'/')) {
+                i += 1;
+            }
+            i = (i + 2).min(bytes.len());
+            continue;
+        }
+        let token = match c {
+            b'\'' | b'"' | b'`' => {
+                let (s, end) = read_quoted(sql, i, c)?;
+                i = end;
+                if c == b'\'' {
+                    Token::Str(s)
+                } else {
+                    Token::QuotedIdent(s)
+                }
+            }
+            b'[' => {
+                let end = sql[i..]
+                    .find(']')
+                    .ok_or_else(|| malformed("unterminated [identifier]"))?;
+                let s = sql[i + 1..i + end].to_string();
+                i += end + 1;
+                Token::QuotedIdent(s)
+            }
+            b'x' | b'X' if bytes.get(i + 1) == Some(&b'\'') => {
+                let (s, end) = read_quoted(sql, i + 1, b'\'')?;
+                i = end;
+                Token::Blob(parse_hex(&s)?)
+            }
+            c if c.is_ascii_digit() || (c == b'.' && bytes.get(i + 1).is_some_and(|b| b.is_ascii_digit())) => {
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'.') {
+                    // exponent sign as in 1e-5
+                    if (bytes[i] == b'e' || bytes[i] == b'E')
+                        && matches!(bytes.get(i + 1), Some(b'+') | Some(b'-'))
+                    {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                Token::Number(sql[start..i].to_string())
+            }
+            c if c.is_ascii_alphabetic() || c == b'_' || c >= 0x80 => {
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_' || bytes[i] == b'$' || bytes[i] >= 0x80) {
+                    i += 1;
+                }
+                Token::Word(sql[start..i].to_string())
+            }
+            c => {
+                i += 1;
+                Token::Symbol(c as char)
+            }
+        };
+        tokens.push((token, start, i));
+    }
+    Ok(tokens)
+}
+
+/// reads a quoted string starting at `start`, a doubled quote stands for the quote itself
+fn read_quoted(sql: &str, start: usize, quote: u8) -> Result<(String, usize)> {
+    let bytes = sql.as_bytes();
+    let mut s: Vec<u8> = vec![];
+    let mut i = start + 1;
+    loop {
+        match bytes.get(i) {
+            None => return Err(malformed("unterminated quoted string")),
+            Some(&b) if b == quote => {
+                if bytes.get(i + 1) == Some(&quote) {
+                    s.push(quote);
+                    i += 2;
+                } else {
+                    return Ok((String::from_utf8_lossy(&s).into_owned(), i + 1));
+                }
+            }
+            Some(&b) => {
+                s.push(b);
+                i += 1;
+            }
+        }
+    }
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return Err(malformed("odd length blob literal"));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| malformed("invalid blob literal")))
+        .collect()
+}
+
+//######################################################
+// parser
+//######################################################
+
+/// words that end a declared column type
+const COLUMN_CONSTRAINT_START: [&str; 11] = [
+    "CONSTRAINT", "PRIMARY", "NOT", "NULL", "UNIQUE", "CHECK", "DEFAULT", "COLLATE", "REFERENCES",
+    "GENERATED", "AS",
+];
+
+struct Parser<'a> {
+    sql: &'a str,
+    tokens: Vec<(Token, usize, usize)>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(sql: &'a str) -> Result<Parser<'a>> {
+        Ok(Parser {
+            sql,
+            tokens: tokenize(sql)?,
+            pos: 0,
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _, _)| t)
+    }
+
+    fn peek_at(&self, n: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + n).map(|(t, _, _)| t)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let t = self
+            .tokens
+            .get(self.pos)
+            .map(|(t, _, _)| t.clone())
+            .ok_or_else(|| malformed("unexpected end of statement"))?;
+        self.pos += 1;
+        Ok(t)
+    }
+
+    fn is_keyword_at(&self, n: usize, keyword: &str) -> bool {
+        matches!(self.peek_at(n), Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        self.is_keyword_at(0, keyword)
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.is_keyword(keyword) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(malformed(format!("expected {} but found {:?}", keyword, self.peek())))
+        }
+    }
+
+    fn is_symbol(&self, c: char) -> bool {
+        self.peek() == Some(&Token::Symbol(c))
+    }
+
+    fn eat_symbol(&mut self, c: char) -> bool {
+        if self.is_symbol(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_symbol(&mut self, c: char) -> Result<()> {
+        if self.eat_symbol(c) {
+            Ok(())
+        } else {
+            Err(malformed(format!("expected '{}' but found {:?}", c, self.peek())))
+        }
+    }
+
+    fn name(&mut self) -> Result<String> {
+        match self.next()? {
+            Token::Word(s) | Token::QuotedIdent(s) | Token::Str(s) => Ok(s),
+            t => Err(malformed(format!("expected a name but found {:?}", t))),
+        }
+    }
+
+    /// `schema.name` or `name`, the schema part is dropped
+    fn qualified_name(&mut self) -> Result<String> {
+        let name = self.name()?;
+        if self.eat_symbol('.') {
+            return self.name();
+        }
+        Ok(name)
+    }
+
+    /// source text between the start of token `from` and the end of token `to - 1`
+    fn text(&self, from: usize, to: usize) -> String {
+        if from >= to {
+            return String::new();
+        }
+        self.sql[self.tokens[from].1..self.tokens[to - 1].2].to_string()
+    }
+
+    /// consumes a parenthesized group and returns the text inside it
+    fn parenthesized(&mut self) -> Result<String> {
+        self.expect_symbol('(')?;
+        let start = self.pos;
+        let mut depth = 1;
+        loop {
+            match self.next()? {
+                Token::Symbol('(') => depth += 1,
+                Token::Symbol(')') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(self.text(start, self.pos - 1));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn if_not_exists(&mut self) -> Result<()> {
+        if self.eat_keyword("IF") {
+            self.expect_keyword("NOT")?;
+            self.expect_keyword("EXISTS")?;
+        }
+        Ok(())
+    }
+
+    fn conflict_clause(&mut self) -> Result<()> {
+        if self.is_keyword("ON") && self.is_keyword_at(1, "CONFLICT") {
+            self.pos += 2;
+            self.name()?;
+        }
+        Ok(())
+    }
+
+    fn sort_order(&mut self) -> SortOrder {
+        if self.eat_keyword("DESC") {
+            SortOrder::Desc
+        } else {
+            self.eat_keyword("ASC");
+            SortOrder::Asc
+        }
+    }
+
+    fn foreign_key_clause(&mut self) -> Result<()> {
+        self.name()?;
+        if self.is_symbol('(') {
+            self.parenthesized()?;
+        }
+        loop {
+            if self.eat_keyword("ON") {
+                self.name()?; // DELETE or UPDATE
+                if self.eat_keyword("SET") || self.eat_keyword("NO") {
+                    self.name()?;
+                } else {
+                    self.name()?; // CASCADE or RESTRICT
+                }
+            } else if self.eat_keyword("MATCH") {
+                self.name()?;
+            } else if self.is_keyword("DEFERRABLE") || (self.is_keyword("NOT") && self.is_keyword_at(1, "DEFERRABLE")) {
+                self.eat_keyword("NOT");
+                self.expect_keyword("DEFERRABLE")?;
+                if self.eat_keyword("INITIALLY") {
+                    self.name()?;
+                }
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    /// `expr [COLLATE name] [ASC|DESC]` as found in index and key definitions
+    fn indexed_column(&mut self) -> Result<IndexedColumn> {
+        let start = self.pos;
+        let mut depth = 0;
+        while let Some(t) = self.peek() {
+            match t {
+                Token::Symbol('(') => depth += 1,
+                Token::Symbol(')') if depth == 0 => break,
+                Token::Symbol(')') => depth -= 1,
+                Token::Symbol(',') if depth == 0 => break,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        let mut end = self.pos;
+        let mut order = SortOrder::Asc;
+        let mut collation = None;
+        let is_word = |t: &Token, kw: &str| matches!(t, Token::Word(w) if w.eq_ignore_ascii_case(kw));
+        if end > start + 1 {
+            let last = &self.tokens[end - 1].0;
+            if is_word(last, "DESC") {
+                order = SortOrder::Desc;
+                end -= 1;
+            } else if is_word(last, "ASC") {
+                end -= 1;
+            }
+        }
+        if end > start + 2 && is_word(&self.tokens[end - 2].0, "COLLATE") {
+            if let Token::Word(c) | Token::QuotedIdent(c) | Token::Str(c) = &self.tokens[end - 1].0 {
+                collation = Some(c.clone());
+            }
+            end -= 2;
+        }
+        if end == start {
+            return Err(malformed("empty indexed column"));
+        }
+        let name = match &self.tokens[start..end] {
+            [(Token::Word(n), _, _)] | [(Token::QuotedIdent(n), _, _)] | [(Token::Str(n), _, _)] => Some(n.clone()),
+            _ => None,
+        };
+        Ok(IndexedColumn {
+            name,
+            expr: self.text(start, end),
+            collation,
+            order,
+        })
+    }
+
+    fn indexed_columns(&mut self) -> Result<Vec<IndexedColumn>> {
+        self.expect_symbol('(')?;
+        let mut columns = vec![self.indexed_column()?];
+        while self.eat_symbol(',') {
+            columns.push(self.indexed_column()?);
+        }
+        self.expect_symbol(')')?;
+        Ok(columns)
+    }
+
+    fn column_def(&mut self, table: &mut TableDef, column_pk_desc: &mut bool) -> Result<()> {
+        let name = self.name()?;
+        let type_start = self.pos;
+        while let Some(t) = self.peek() {
+            match t {
+                Token::Word(w) if COLUMN_CONSTRAINT_START.iter().any(|k| w.eq_ignore_ascii_case(k)) => break,
+                Token::Word(_) | Token::QuotedIdent(_) => self.pos += 1,
+                Token::Symbol('(') => {
+                    self.parenthesized()?;
+                    break;
+                }
+                _ => break,
+            }
+        }
+        let declared_type = if self.pos > type_start {
+            Some(self.text(type_start, self.pos))
+        } else {
+            None
+        };
+        let mut column = ColumnDef {
+            name,
+            affinity: Affinity::from_declared_type(declared_type.as_deref()),
+            declared_type,
+            primary_key: false,
+            not_null: false,
+            default: None,
+            collation: None,
+            unique: false,
+            generated: None,
+        };
+
+        loop {
+            if self.eat_keyword("CONSTRAINT") {
+                self.name()?;
+            } else if self.eat_keyword("PRIMARY") {
+                self.expect_keyword("KEY")?;
+                let order = self.sort_order();
+                if order == SortOrder::Desc {
+                    *column_pk_desc = true;
+                }
+                self.conflict_clause()?;
+                self.eat_keyword("AUTOINCREMENT");
+                column.primary_key = true;
+                table.primary_key = vec![column.name.clone()];
+                table.key_constraints.push(KeyConstraint {
+                    primary_key: true,
+                    columns: vec![IndexedColumn::named(&column.name, order)],
+                });
+            } else if self.eat_keyword("NOT") {
+                self.expect_keyword("NULL")?;
+                self.conflict_clause()?;
+                column.not_null = true;
+            } else if self.eat_keyword("NULL") {
+                self.conflict_clause()?;
+            } else if self.eat_keyword("UNIQUE") {
+                self.conflict_clause()?;
+                column.unique = true;
+                table.unique.push(vec![column.name.clone()]);
+                table.key_constraints.push(KeyConstraint {
+                    primary_key: false,
+                    columns: vec![IndexedColumn::named(&column.name, SortOrder::Asc)],
+                });
+            } else if self.eat_keyword("CHECK") {
+                self.parenthesized()?;
+            } else if self.eat_keyword("DEFAULT") {
+                column.default = Some(if self.is_symbol('(') {
+                    self.parenthesized()?
+                } else {
+                    let start = self.pos;
+                    if self.is_symbol('-') || self.is_symbol('+') {
+                        self.pos += 1;
+                    }
+                    self.next()?;
+                    self.text(start, self.pos)
+                });
+            } else if self.eat_keyword("COLLATE") {
+                column.collation = Some(self.name()?);
+            } else if self.eat_keyword("REFERENCES") {
+                self.foreign_key_clause()?;
+            } else if self.is_keyword("GENERATED") || self.is_keyword("AS") {
+                if self.eat_keyword("GENERATED") {
+                    self.expect_keyword("ALWAYS")?;
+                }
+                self.expect_keyword("AS")?;
+                self.parenthesized()?;
+                column.generated = Some(if self.eat_keyword("STORED") {
+                    Generated::Stored
+                } else {
+                    self.eat_keyword("VIRTUAL");
+                    Generated::Virtual
+                });
+            } else {
+                break;
+            }
+        }
+        table.columns.push(column);
+        Ok(())
+    }
+
+    fn is_table_constraint(&self) -> bool {
+        ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"]
+            .iter()
+            .any(|k| self.is_keyword(k))
+    }
+
+    fn table_constraint(&mut self, table: &mut TableDef) -> Result<()> {
+        if self.eat_keyword("CONSTRAINT") {
+            self.name()?;
+        }
+        if self.eat_keyword("PRIMARY") {
+            self.expect_keyword("KEY")?;
+            let columns = self.indexed_columns()?;
+            self.conflict_clause()?;
+            table.primary_key = columns.iter().map(|c| c.name.clone().unwrap_or(c.expr.clone())).collect();
+            table.key_constraints.push(KeyConstraint {
+                primary_key: true,
+                columns,
+            });
+        } else if self.eat_keyword("UNIQUE") {
+            let columns = self.indexed_columns()?;
+            self.conflict_clause()?;
+            table.unique.push(columns.iter().map(|c| c.name.clone().unwrap_or(c.expr.clone())).collect());
+            table.key_constraints.push(KeyConstraint {
+                primary_key: false,
+                columns,
+            });
+        } else if self.eat_keyword("CHECK") {
+            self.parenthesized()?;
+        } else if self.eat_keyword("FOREIGN") {
+            self.expect_keyword("KEY")?;
+            self.parenthesized()?;
+            self.expect_keyword("REFERENCES")?;
+            self.foreign_key_clause()?;
+        } else {
+            return Err(malformed(format!("unexpected {:?} in table constraint", self.peek())));
+        }
+        Ok(())
+    }
+
+    fn create_table(&mut self) -> Result<TableDef> {
+        self.expect_keyword("CREATE")?;
+        if !self.eat_keyword("TEMP") {
+            self.eat_keyword("TEMPORARY");
+        }
+        if self.is_keyword("VIRTUAL") {
+            return Err(Error::UnsupportedFeature("virtual tables".into()));
+        }
+        self.expect_keyword("TABLE")?;
+        self.if_not_exists()?;
+        let mut table = TableDef {
+            name: self.qualified_name()?,
+            columns: vec![],
+            primary_key: vec![],
+            unique: vec![],
+            key_constraints: vec![],
+            without_rowid: false,
+            strict: false,
+            rowid_alias: None,
+        };
+        if self.is_keyword("AS") {
+            return Err(Error::UnsupportedFeature("CREATE TABLE ... AS SELECT".into()));
+        }
+        self.expect_symbol('(')?;
+        let mut column_pk_desc = false;
+        loop {
+            if self.is_table_constraint() {
+                self.table_constraint(&mut table)?;
+            } else {
+                self.column_def(&mut table, &mut column_pk_desc)?;
+            }
+            if !self.eat_symbol(',') {
+                break;
+            }
+        }
+        self.expect_symbol(')')?;
+        loop {
+            if self.eat_keyword("WITHOUT") {
+                self.expect_keyword("ROWID")?;
+                table.without_rowid = true;
+            } else if self.eat_keyword("STRICT") {
+                table.strict = true;
+            } else if !self.eat_symbol(',') {
+                break;
+            }
+        }
+
+        for name in &table.primary_key {
+            if let Some(c) = table.columns.iter_mut().find(|c| c.name.eq_ignore_ascii_case(name)) {
+                c.primary_key = true;
+            }
+        }
+        // only a lone column declared exactly as INTEGER aliases the rowid, and
+        // "INTEGER PRIMARY KEY DESC" on the column itself is the documented exception
+        if !table.without_rowid && table.primary_key.len() == 1 && !column_pk_desc {
+            table.rowid_alias = table.column_index(&table.primary_key[0]).filter(|&i| {
+                table.columns[i]
+                    .declared_type
+                    .as_deref()
+                    .is_some_and(|t| t.eq_ignore_ascii_case("INTEGER"))
+            });
+        }
+        Ok(table)
+    }
+
+    fn create_index(&mut self) -> Result<IndexDef> {
+        self.expect_keyword("CREATE")?;
+        let unique = self.eat_keyword("UNIQUE");
+        self.expect_keyword("INDEX")?;
+        self.if_not_exists()?;
+        let name = self.qualified_name()?;
+        self.expect_keyword("ON")?;
+        let table = self.name()?;
+        let columns = self.indexed_columns()?;
+        let where_clause = if self.eat_keyword("WHERE") {
+            let start = self.pos;
+            let mut end = self.tokens.len();
+            if end > start && self.tokens[end - 1].0 == Token::Symbol(';') {
+                end -= 1;
+            }
+            self.pos = self.tokens.len();
+            Some(self.text(start, end))
+        } else {
+            None
+        };
+        Ok(IndexDef {
+            name,
+            table,
+            unique,
+            columns,
+            where_clause,
+        })
+    }
+}
+
+pub fn parse_create_table(sql: &str) -> Result<TableDef> {
+    Parser::new(sql)?.create_table()
+}
+
+pub fn parse_create_index(sql: &str) -> Result<IndexDef> {
+    Parser::new(sql)?.create_index()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rowid_alias() {
+        let cases = [
+            ("CREATE TABLE t(id INTEGER PRIMARY KEY, a)", Some(0)),
+            ("CREATE TABLE t(a, id integer primary key)", Some(1)),
+            ("CREATE TABLE t(id INTEGER PRIMARY KEY ASC, a)", Some(0)),
+            // DESC on the column itself keeps the rowid separate, in a table constraint it does not
+            ("CREATE TABLE t(id INTEGER PRIMARY KEY DESC, a)", None),
+            ("CREATE TABLE t(id INTEGER, a, PRIMARY KEY(id DESC))", Some(0)),
+            ("CREATE TABLE t(id INT PRIMARY KEY, a)", None),
+            ("CREATE TABLE t(id INTEGER, a INTEGER, PRIMARY KEY(id, a))", None),
+            ("CREATE TABLE t(id INTEGER PRIMARY KEY, a) WITHOUT ROWID", None),
+        ];
+        for (sql, alias) in cases {
+            assert_eq!(parse_create_table(sql).unwrap().rowid_alias, alias, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn quoted_identifiers() {
+        let cases = [
+            ("CREATE TABLE \"my table\"(\"first col\" TEXT, [b], `c` INT, 'd')", "my table", vec!["first col", "b", "c", "d"]),
+            ("CREATE TABLE \"a\"\"b\"(\"x\"\"y\", `p``q`)", "a\"b", vec!["x\"y", "p`q"]),
+            ("CREATE TABLE IF NOT EXISTS main.[t 1](\"select\" INTEGER PRIMARY KEY)", "t 1", vec!["select"]),
+        ];
+        for (sql, name, columns) in cases {
+            let table = parse_create_table(sql).unwrap();
+            assert_eq!(table.name, name, "{}", sql);
+            assert_eq!(table.columns.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), columns, "{}", sql);
+        }
+        let table = parse_create_table("CREATE TABLE t(\"Id\" INTEGER PRIMARY KEY, a)").unwrap();
+        assert_eq!(table.rowid_alias, Some(0));
+        assert_eq!(table.column_index("ID"), Some(0));
+    }
+
+    #[test]
+    fn indexed_columns() {
+        // (sql, per column: name, expression, collation, order)
+        let cases = [
+            (
+                "CREATE INDEX i ON t(a COLLATE NOCASE DESC, b ASC, c)",
+                vec![
+                    (Some("a"), "a", Some("NOCASE"), SortOrder::Desc),
+                    (Some("b"), "b", None, SortOrder::Asc),
+                    (Some("c"), "c", None, SortOrder::Asc),
+                ],
+            ),
+            (
+                "CREATE UNIQUE INDEX IF NOT EXISTS \"i\" ON t(lower(a) DESC, \"b c\" COLLATE \"rtrim\")",
+                vec![
+                    (None, "lower(a)", None, SortOrder::Desc),
+                    (Some("b c"), "\"b c\"", Some("rtrim"), SortOrder::Asc),
+                ],
+            ),
+            (
+                "CREATE INDEX i ON t(a + b COLLATE binary, [desc] desc)",
+                vec![
+                    (None, "a + b", Some("binary"), SortOrder::Asc),
+                    (Some("desc"), "[desc]", None, SortOrder::Desc),
+                ],
+            ),
+        ];
+        for (sql, expected) in cases {
+            let index = parse_create_index(sql).unwrap();
+            let columns: Vec<_> = index
+                .columns
+                .iter()
+                .map(|c| (c.name.as_deref(), c.expr.as_str(), c.collation.as_deref(), c.order))
+                .collect();
+            assert_eq!(columns, expected, "{}", sql);
+            assert_eq!(index.where_clause, None, "{}", sql);
+        }
+        let index = parse_create_index("CREATE UNIQUE INDEX i ON t(a)").unwrap();
+        assert!(index.unique);
+        assert_eq!((index.name.as_str(), index.table.as_str()), ("i", "t"));
+    }
+
+    #[test]
+    fn partial_index() {
+        let cases = [
+            ("CREATE INDEX i ON t(a) WHERE a IS NOT NULL", Some("a IS NOT NULL")),
+            ("CREATE INDEX i ON t(a, b) WHERE a > 0 AND (b = 'x' OR b IS NULL);", Some("a > 0 AND (b = 'x' OR b IS NULL)")),
+            ("CREATE INDEX i ON t(a)", None),
+        ];
+        for (sql, where_clause) in cases {
+            assert_eq!(parse_create_index(sql).unwrap().where_clause.as_deref(), where_clause, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn table_options() {
+        // (sql, WITHOUT ROWID, STRICT)
+        let cases = [
+            ("CREATE TABLE t(a TEXT PRIMARY KEY, b)", false, false),
+            ("CREATE TABLE t(a TEXT PRIMARY KEY, b) WITHOUT ROWID", true, false),
+            ("CREATE TABLE t(a TEXT PRIMARY KEY, b) STRICT", false, true),
+            ("CREATE TABLE t(a TEXT PRIMARY KEY, b) STRICT, WITHOUT ROWID", true, true),
+            ("create table t(a text primary key, b) without rowid , strict", true, true),
+        ];
+        for (sql, without_rowid, strict) in cases {
+            let table = parse_create_table(sql).unwrap();
+            assert_eq!((table.without_rowid, table.strict), (without_rowid, strict), "{}", sql);
+        }
+    }
+
+    /// numbering checked against the indexes sqlite creates for the same tables
+    #[test]
+    fn automatic_index_numbering() {
+        let cases = [
+            ("CREATE TABLE t(a UNIQUE, b, c, PRIMARY KEY(b, c), UNIQUE(c))", vec![vec!["a"], vec!["b", "c"], vec!["c"]]),
+            ("CREATE TABLE t(id INTEGER PRIMARY KEY, a UNIQUE)", vec![vec!["a"]]),
+            ("CREATE TABLE t(id INTEGER PRIMARY KEY DESC, a UNIQUE)", vec![vec!["id"], vec!["a"]]),
+            ("CREATE TABLE t(a UNIQUE, b, UNIQUE(a), UNIQUE(b))", vec![vec!["a"], vec!["b"]]),
+            ("CREATE TABLE t(a, b, UNIQUE(a, b), UNIQUE(b, a))", vec![vec!["a", "b"], vec!["b", "a"]]),
+            ("CREATE TABLE t(a, b, UNIQUE(a COLLATE NOCASE), UNIQUE(a))", vec![vec!["a"], vec!["a"]]),
+        ];
+        for (sql, indexes) in cases {
+            let table = parse_create_table(sql).unwrap();
+            for (i, expected) in indexes.iter().enumerate() {
+                let name = format!("sqlite_autoindex_t_{}", i + 1);
+                let index = table.automatic_index(&name).unwrap_or_else(|| panic!("{} in {}", name, sql));
+                assert!(index.unique);
+                let columns: Vec<_> = index.columns.iter().map(|c| c.name.as_deref().unwrap()).collect();
+                assert_eq!(&columns, expected, "{} in {}", name, sql);
+            }
+            let past = format!("sqlite_autoindex_t_{}", indexes.len() + 1);
+            assert!(table.automatic_index(&past).is_none(), "{} in {}", past, sql);
+        }
+    }
+}
@@ -1,41 +1,232 @@
-use crate::tools::helper::{read_u16, read_u32, read_var_int};
+use crate::tools::ddl::SortOrder;
+use crate::tools::error::{Error, Result};
+use crate::tools::helper::{read_u16, read_u32, write_u16, write_u32, write_var_int};
+use crate::tools::view::{CellRef, PageKind, PageRef, RecordView, ValueRef};
 use std::cmp::{self, Ordering};
 
 pub const HEADER_SIZE: usize = 100;
-pub const PAGE_SIZE: usize = 4096;
 const SQLITE_HEADER_STRING: &str = "SQLite format 3\0";
 
 //######################################################
 //sqlite header
 //######################################################
 
+/// the 100 byte database header, integers are stored big-endian
+#[derive(Debug, Clone)]
 pub struct SqliteHeader {
-    version_string: String,
-    page_size: u16,
-    // version_valid_for: u64,
-    version: u32,
+    /// raw value at offset 16, 1 stands for 65536, see `page_size()`
+    pub page_size: u16,
+    /// 1 for legacy rollback journal, 2 for WAL
+    pub file_format_write_version: u8,
+    pub file_format_read_version: u8,
+    /// unused bytes at the end of every page
+    pub reserved_space: u8,
+    /// always 64
+    pub max_embedded_payload_fraction: u8,
+    /// always 32
+    pub min_embedded_payload_fraction: u8,
+    /// always 32
+    pub leaf_payload_fraction: u8,
+    pub file_change_counter: u32,
+    /// size of the file in pages, only trusted when `database_size_valid()`
+    pub database_size: u32,
+    pub first_freelist_trunk_page: u32,
+    pub freelist_page_count: u32,
+    pub schema_cookie: u32,
+    /// 1 to 4
+    pub schema_format: u32,
+    pub default_cache_size: i32,
+    /// non zero in auto-vacuum and incremental-vacuum databases
+    pub largest_root_page: u32,
+    pub text_encoding: TextEncoding,
+    pub user_version: i32,
+    /// non zero for incremental-vacuum mode
+    pub incremental_vacuum: u32,
+    pub application_id: u32,
+    /// change counter at the time `version` was written
+    pub version_valid_for: u32,
+    /// SQLITE_VERSION_NUMBER of the library that last wrote the file
+    pub version: u32,
 }
 
 impl SqliteHeader {
-    pub fn new(buf: &[u8; 100]) -> SqliteHeader {
-        let page_size = read_u16(buf, &mut 16);
-        let version = read_u32(buf, &mut 96);
+    pub fn new(buf: &[u8; 100]) -> Result<SqliteHeader> {
+        let page_size = read_u16(buf, &mut 16)?;
+        let version = read_u32(buf, &mut 96)?;
         let string_bytes = &buf[..16];
-        let version_string = String::from_utf8(string_bytes.into()).unwrap();
-        println!(
-            "partition: {},{}, {}, {}",
-            page_size,
-            version,
-            version_string,
-            SQLITE_HEADER_STRING == &version_string
-        );
+        let version_string = String::from_utf8_lossy(string_bytes);
         if SQLITE_HEADER_STRING != version_string {
-            panic!("invalid file: header string missmatch");
+            return Err(Error::Corrupt {
+                page: 1,
+                reason: "invalid file: header string missmatch".into(),
+            });
         }
-        SqliteHeader {
+        let text_encoding = read_u32(buf, &mut 56)?;
+        let text_encoding = TextEncoding::from_header(text_encoding).ok_or_else(|| Error::Corrupt {
+            page: 1,
+            reason: format!("invalid text encoding {}", text_encoding),
+        })?;
+        let header = SqliteHeader {
             page_size,
+            file_format_write_version: buf[18],
+            file_format_read_version: buf[19],
+            reserved_space: buf[20],
+            max_embedded_payload_fraction: buf[21],
+            min_embedded_payload_fraction: buf[22],
+            leaf_payload_fraction: buf[23],
+            file_change_counter: read_u32(buf, &mut 24)?,
+            database_size: read_u32(buf, &mut 28)?,
+            first_freelist_trunk_page: read_u32(buf, &mut 32)?,
+            freelist_page_count: read_u32(buf, &mut 36)?,
+            schema_cookie: read_u32(buf, &mut 40)?,
+            schema_format: read_u32(buf, &mut 44)?,
+            default_cache_size: read_u32(buf, &mut 48)? as i32,
+            largest_root_page: read_u32(buf, &mut 52)?,
+            text_encoding,
+            user_version: read_u32(buf, &mut 60)? as i32,
+            incremental_vacuum: read_u32(buf, &mut 64)?,
+            application_id: read_u32(buf, &mut 68)?,
+            version_valid_for: read_u32(buf, &mut 92)?,
             version,
-            version_string,
+        };
+        header.validate()?;
+        Ok(header)
+    }
+
+    /// checks the invariants the file format documents
+    pub fn validate(&self) -> Result<()> {
+        if self.page_size != 1 && (self.page_size < 512 || !self.page_size.is_power_of_two()) {
+            return Err(header_corrupt(format!("invalid page size {}", self.page_size)));
+        }
+        for version in [self.file_format_read_version, self.file_format_write_version] {
+            if version != 1 && version != 2 {
+                return Err(Error::UnsupportedFeature(format!("file format version {}", version)));
+            }
+        }
+        if (self.max_embedded_payload_fraction, self.min_embedded_payload_fraction, self.leaf_payload_fraction) != (64, 32, 32) {
+            return Err(header_corrupt("payload fractions must be 64, 32 and 32"));
+        }
+        if self.usable_size() < 480 {
+            return Err(header_corrupt(format!(
+                "{} reserved bytes leave less than 480 usable bytes per page",
+                self.reserved_space
+            )));
+        }
+        if self.schema_format > 4 {
+            return Err(Error::UnsupportedFeature(format!("schema format {}", self.schema_format)));
+        }
+        if self.largest_root_page == 0 && self.incremental_vacuum != 0 {
+            return Err(header_corrupt("incremental vacuum set without auto-vacuum"));
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> [u8; 100] {
+        let mut buf = [0u8; HEADER_SIZE];
+        buf[..16].copy_from_slice(SQLITE_HEADER_STRING.as_bytes());
+        write_u16(&mut buf, 16, self.page_size);
+        buf[18] = self.file_format_write_version;
+        buf[19] = self.file_format_read_version;
+        buf[20] = self.reserved_space;
+        buf[21] = self.max_embedded_payload_fraction;
+        buf[22] = self.min_embedded_payload_fraction;
+        buf[23] = self.leaf_payload_fraction;
+        write_u32(&mut buf, 24, self.file_change_counter);
+        write_u32(&mut buf, 28, self.database_size);
+        write_u32(&mut buf, 32, self.first_freelist_trunk_page);
+        write_u32(&mut buf, 36, self.freelist_page_count);
+        write_u32(&mut buf, 40, self.schema_cookie);
+        write_u32(&mut buf, 44, self.schema_format);
+        write_u32(&mut buf, 48, self.default_cache_size as u32);
+        write_u32(&mut buf, 52, self.largest_root_page);
+        write_u32(&mut buf, 56, self.text_encoding.to_header());
+        write_u32(&mut buf, 60, self.user_version as u32);
+        write_u32(&mut buf, 64, self.incremental_vacuum);
+        write_u32(&mut buf, 68, self.application_id);
+        write_u32(&mut buf, 92, self.version_valid_for);
+        write_u32(&mut buf, 96, self.version);
+        buf
+    }
+
+    /// page size in bytes, the stored value 1 stands for 65536
+    pub fn page_size(&self) -> usize {
+        if self.page_size == 1 {
+            65536
+        } else {
+            self.page_size as usize
+        }
+    }
+
+    /// bytes of each page available to b-tree and overflow content, extensions
+    /// like checksums or encryption keep their data in the reserved tail
+    pub fn usable_size(&self) -> usize {
+        self.page_size() - self.reserved_space as usize
+    }
+
+    /// older versions of sqlite do not update the in-header size,
+    /// they can be detected by a stale `version_valid_for`
+    pub fn database_size_valid(&self) -> bool {
+        self.database_size != 0 && self.version_valid_for == self.file_change_counter
+    }
+}
+
+fn header_corrupt(reason: impl Into<String>) -> Error {
+    Error::Corrupt {
+        page: 1,
+        reason: reason.into(),
+    }
+}
+
+/// encoding of every text value in the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextEncoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl TextEncoding {
+    pub fn from_header(value: u32) -> Option<TextEncoding> {
+        match value {
+            1 => Some(TextEncoding::Utf8),
+            2 => Some(TextEncoding::Utf16Le),
+            3 => Some(TextEncoding::Utf16Be),
+            _ => None,
+        }
+    }
+
+    pub fn to_header(self) -> u32 {
+        match self {
+            TextEncoding::Utf8 => 1,
+            TextEncoding::Utf16Le => 2,
+            TextEncoding::Utf16Be => 3,
+        }
+    }
+
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        match self {
+            TextEncoding::Utf8 => text.as_bytes().to_vec(),
+            TextEncoding::Utf16Le => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+            TextEncoding::Utf16Be => text.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+        }
+    }
+
+    /// sqlite stores invalid sequences as they come, those are replaced here
+    pub fn decode(&self, data: &[u8]) -> String {
+        let units = data.chunks_exact(2);
+        match self {
+            TextEncoding::Utf8 => String::from_utf8_lossy(data).into_owned(),
+            TextEncoding::Utf16Le => {
+                char::decode_utf16(units.map(|u| u16::from_le_bytes([u[0], u[1]])))
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect()
+            }
+            TextEncoding::Utf16Be => {
+                char::decode_utf16(units.map(|u| u16::from_be_bytes([u[0], u[1]])))
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect()
+            }
         }
     }
 }
@@ -49,114 +240,165 @@ pub enum Page {
     TableBTreeInteriorPage(TableBTreeInteriorPage),
 	IndexBTreeLeafPage(IndexBTreeLeafPage),
 	IndexBTreeInteriorPage(IndexBTreeInteriorPage),
+	PointerMapPage(PointerMapPage),
+}
+
+/// the page without the reserved bytes at its end
+pub(crate) fn usable_area(page: &[u8], usable_size: usize) -> &[u8] {
+    &page[..usable_size.min(page.len())]
 }
 
 //######################################################
 
 pub struct TableBTreeLeafPage {
-    raw_buf: Vec<u8>,
     pub header: BTreePageLeafHeader,
     pub cells: Vec<TableBTreeLeafCell>,
 }
 
 impl TableBTreeLeafPage {
-    pub fn new(page: &[u8]) -> TableBTreeLeafPage {
-        let raw_buf = Vec::from(page);
-        let header = BTreePageLeafHeader::new(&page[0..8]);
-        let mut cells: Vec<TableBTreeLeafCell> = Vec::new();
-        for i in 0..header.cell_count {
-            let offset = read_u16(&page, &mut((8 + i * 2) as usize)) as u16;
-            let cell = TableBTreeLeafCell::new(&page, offset as usize);
-            cells.push(cell);
-        }
-        TableBTreeLeafPage {
-            raw_buf,
+    /// `header_offset` is 100 for page 1 and 0 for every other page,
+    /// cells are only read from the first `usable_size` bytes
+    pub fn new(page: &[u8], header_offset: usize, usable_size: usize) -> Result<TableBTreeLeafPage> {
+        let page = usable_area(page, usable_size);
+        let header = BTreePageLeafHeader::new(page, header_offset)?;
+        let cells = PageRef::new(page, header_offset, usable_size)?
+            .cells()
+            .map(|cell| cell.map(|c| c.to_table_leaf_cell()))
+            .collect::<Result<Vec<TableBTreeLeafCell>>>()?;
+        Ok(TableBTreeLeafPage {
             header,
             cells,
-        }
+        })
     }
 }
 
 //######################################################
 
 pub struct TableBTreeInteriorPage {
-    raw_buf: Vec<u8>,
     pub header: BTreePageInteriorHeader,
     pub cells: Vec<TableBTreeInteriorCell>,
 }
 
 impl TableBTreeInteriorPage {
-    pub fn new(page: &[u8]) -> TableBTreeInteriorPage {
-        let raw_buf = Vec::from(page);
-        let header = BTreePageInteriorHeader::new(&page[0..12]);
-        let mut cells: Vec<TableBTreeInteriorCell> = Vec::new();
-        for i in 0..header.cell_count {
-            let offset = read_u16(&page, &mut ((12 + i * 2) as usize)) as u16;
-            let cell = TableBTreeInteriorCell::new(&page, offset as usize);
-            cells.push(cell);
-        }
-        TableBTreeInteriorPage {
-            raw_buf,
+    pub fn new(page: &[u8], header_offset: usize, usable_size: usize) -> Result<TableBTreeInteriorPage> {
+        let page = usable_area(page, usable_size);
+        let header = BTreePageInteriorHeader::new(page, header_offset)?;
+        let cells = PageRef::new(page, header_offset, usable_size)?
+            .cells()
+            .map(|cell| cell.map(|c| c.to_table_interior_cell()))
+            .collect::<Result<Vec<TableBTreeInteriorCell>>>()?;
+        Ok(TableBTreeInteriorPage {
             header,
             cells,
-        }
+        })
     }
 }
 
 //######################################################
 
 pub struct IndexBTreeLeafPage {
-    raw_buf: Vec<u8>,
     pub header: BTreePageLeafHeader,
     pub cells: Vec<IndexBTreeLeafCell>,
 }
 
 impl IndexBTreeLeafPage {
-    pub fn new(page: &[u8]) -> IndexBTreeLeafPage {
-        let raw_buf = Vec::from(page);
-        let header = BTreePageLeafHeader::new(&page[0..8]);
-        let mut cells: Vec<IndexBTreeLeafCell> = Vec::new();
-        for i in 0..header.cell_count {
-            let offset = read_u16(&page, &mut((8 + i * 2) as usize)) as u16;
-            let cell = IndexBTreeLeafCell::new(&page, offset as usize);
-            cells.push(cell);
-        }
-        IndexBTreeLeafPage {
-            raw_buf,
+    pub fn new(page: &[u8], header_offset: usize, usable_size: usize) -> Result<IndexBTreeLeafPage> {
+        let page = usable_area(page, usable_size);
+        let header = BTreePageLeafHeader::new(page, header_offset)?;
+        let cells = PageRef::new(page, header_offset, usable_size)?
+            .cells()
+            .map(|cell| cell.map(|c| c.to_index_leaf_cell()))
+            .collect::<Result<Vec<IndexBTreeLeafCell>>>()?;
+        Ok(IndexBTreeLeafPage {
             header,
             cells,
-        }
+        })
     }
 }
 
 //######################################################
 
 pub struct IndexBTreeInteriorPage {
-    raw_buf: Vec<u8>,
     pub header: BTreePageInteriorHeader,
     pub cells: Vec<IndexBTreeInteriorCell>,
 }
 
 
 impl IndexBTreeInteriorPage {
-    pub fn new(page: &[u8]) -> IndexBTreeInteriorPage {
-        let raw_buf = Vec::from(page);
-        let header = BTreePageInteriorHeader::new(&page[0..12]);
-        let mut cells: Vec<IndexBTreeInteriorCell> = Vec::new();
-        for i in 0..header.cell_count {
-            let offset = read_u16(&page, &mut((12 + i * 2) as usize)) as u16;
-            let cell = IndexBTreeInteriorCell::new(&page, offset as usize);
-            cells.push(cell);
-        }
-        IndexBTreeInteriorPage {
-            raw_buf,
+    pub fn new(page: &[u8], header_offset: usize, usable_size: usize) -> Result<IndexBTreeInteriorPage> {
+        let page = usable_area(page, usable_size);
+        let header = BTreePageInteriorHeader::new(page, header_offset)?;
+        let cells = PageRef::new(page, header_offset, usable_size)?
+            .cells()
+            .map(|cell| cell.map(|c| c.to_index_interior_cell()))
+            .collect::<Result<Vec<IndexBTreeInteriorCell>>>()?;
+        Ok(IndexBTreeInteriorPage {
             header,
             cells,
-        }
+        })
     }
 }
 
 
+//######################################################
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerMapType {
+    /// root of a b-tree, parent is 0
+    RootPage,
+    /// parent is 0
+    FreePage,
+    /// first page of an overflow chain, parent is the page holding the cell
+    Overflow1,
+    /// later page of an overflow chain, parent is the previous chain page
+    Overflow2,
+    /// non-root b-tree page, parent is the b-tree page pointing to it
+    BTree,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PointerMapEntry {
+    pub page: u32,
+    pub page_type: PointerMapType,
+    pub parent: u32,
+}
+
+/// in auto-vacuum databases, lists type and parent of each page that follows it
+pub struct PointerMapPage {
+    pub entries: Vec<PointerMapEntry>,
+}
+
+impl PointerMapPage {
+    /// `page_number` is the location of this page and `pages` the pages after it that
+    /// it describes, each in the slot of its distance to this page. Slots of other pages,
+    /// like the lock-byte page, are never written and not read.
+    pub fn new(page: &[u8], page_number: u32, pages: &[u32]) -> Result<PointerMapPage> {
+        let mut entries = vec![];
+        for &described in pages {
+            let offset = described
+                .checked_sub(page_number + 1)
+                .ok_or_else(|| Error::corrupt(format!("page {} comes before its pointer map page", described)))?
+                as usize
+                * 5;
+            let page_type = match page.get(offset) {
+                Some(1) => PointerMapType::RootPage,
+                Some(2) => PointerMapType::FreePage,
+                Some(3) => PointerMapType::Overflow1,
+                Some(4) => PointerMapType::Overflow2,
+                Some(5) => PointerMapType::BTree,
+                x => return Err(Error::corrupt(format!("invalid pointer map entry type {:?} at offset {}", x, offset))),
+            };
+            let parent = read_u32(page, &mut (offset + 1))?;
+            entries.push(PointerMapEntry {
+                page: described,
+                page_type,
+                parent,
+            });
+        }
+        Ok(PointerMapPage { entries })
+    }
+}
+
 //######################################################
 // page header
 //######################################################
@@ -177,20 +419,20 @@ pub struct BTreePageLeafHeader {
 }
 
 impl BTreePageLeafHeader {
-    pub fn new(buf: &[u8]) -> BTreePageLeafHeader {
-        let type_flag = buf[0];
-        let freeblock_count = read_u16(buf, &mut 1);
-        let cell_count = read_u16(buf, &mut 3);
-        let cell_content_start = read_u16(buf, &mut 5);
-        let fragmented_free_bytes = buf[7];
-
-        BTreePageLeafHeader {
+    pub fn new(page: &[u8], offset: usize) -> Result<BTreePageLeafHeader> {
+        let type_flag = page[offset];
+        let freeblock_count = read_u16(page, &mut (offset + 1))?;
+        let cell_count = read_u16(page, &mut (offset + 3))?;
+        let cell_content_start = read_u16(page, &mut (offset + 5))?;
+        let fragmented_free_bytes = page[offset + 7];
+
+        Ok(BTreePageLeafHeader {
             type_flag,
             freeblock_count,
             cell_count,
             cell_content_start,
             fragmented_free_bytes,
-        }
+        })
     }
 }
 
@@ -206,22 +448,22 @@ pub struct BTreePageInteriorHeader {
 }
 
 impl BTreePageInteriorHeader {
-    pub fn new(buf: &[u8]) -> BTreePageInteriorHeader {
-        let type_flag = buf[0];
-        let freeblock_count = read_u16(buf, &mut 1);
-        let cell_count = read_u16(buf, &mut 3);
-        let cell_content_start = read_u16(buf, &mut 5);
-        let fragmented_free_bytes = buf[7];
-        let right_most_pointer: u32 = read_u32(buf, &mut 8);
+    pub fn new(page: &[u8], offset: usize) -> Result<BTreePageInteriorHeader> {
+        let type_flag = page[offset];
+        let freeblock_count = read_u16(page, &mut (offset + 1))?;
+        let cell_count = read_u16(page, &mut (offset + 3))?;
+        let cell_content_start = read_u16(page, &mut (offset + 5))?;
+        let fragmented_free_bytes = page[offset + 7];
+        let right_most_pointer: u32 = read_u32(page, &mut (offset + 8))?;
         // println!("right most pointer {}", right_most_pointer);
-        BTreePageInteriorHeader {
+        Ok(BTreePageInteriorHeader {
             type_flag,
             freeblock_count,
             cell_count,
             cell_content_start,
             fragmented_free_bytes,
             right_most_pointer,
-        }
+        })
     }
 }
 
@@ -229,35 +471,18 @@ impl BTreePageInteriorHeader {
 // cell 
 //######################################################
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TableBTreeLeafCell {
     pub payload_size: u64,
-    pub row_id: u64,
+    pub row_id: i64,
     pub payload: Vec<u8>,
+    /// first page of the overflow chain, 0 when the payload fits on the page
     pub overflow_page: u32,
 }
 
 impl TableBTreeLeafCell {
-    pub fn new(page: &[u8], offset: usize) -> TableBTreeLeafCell {
-        // let payload: Vec<u8> = vec![];
-        let overflow_page: u32 = 0;
-
-        let mut payload_size: u64 = 0;
-        let mut offset = offset;
-        offset += read_var_int(page, offset, &mut payload_size);
-
-        let mut row_id: u64 = 0;
-        offset += read_var_int(page, offset, &mut row_id);
-
-        let payload: Vec<u8> = Vec::from(&page[offset..offset + payload_size as usize]);
-        //TODO: handle overflow
-        // println!("row_id {}   payload_size {} payload_size2 {}", row_id, payload_size, payload.len());
-        TableBTreeLeafCell {
-            payload_size,
-            row_id,
-            payload,
-            overflow_page,
-        }
+    pub fn new(page: &[u8], offset: usize, usable_size: usize) -> Result<TableBTreeLeafCell> {
+        CellRef::new(page, offset, PageKind::TableLeaf, usable_size).map(|c| c.to_table_leaf_cell())
     }
 }
 
@@ -266,21 +491,13 @@ impl TableBTreeLeafCell {
 #[derive(Debug)]
 pub struct TableBTreeInteriorCell {
     pub left_child_pointer: u32,
-    pub row_id: u64,
+    pub row_id: i64,
 }
 
 impl TableBTreeInteriorCell {
-    pub fn new(page: &[u8], offset: usize) -> TableBTreeInteriorCell {
-		// let payload: Vec<u8> = vec![];
-        let mut offset = offset;
-        let left_child_pointer: u32 = read_u32(page, &mut offset);
-
-        let mut row_id: u64 = 0;
-        offset += read_var_int(page, offset, &mut row_id);
-        TableBTreeInteriorCell {
-            left_child_pointer,
-            row_id,
-        }
+    pub fn new(page: &[u8], offset: usize) -> Result<TableBTreeInteriorCell> {
+        // table interior cells carry no payload, so the usable size does not matter
+        CellRef::new(page, offset, PageKind::TableInterior, page.len()).map(|c| c.to_table_interior_cell())
     }
 }
 
@@ -293,21 +510,8 @@ pub struct IndexBTreeLeafCell {
 }
 
 impl IndexBTreeLeafCell {
-    pub fn new(page: &[u8], offset: usize) -> IndexBTreeLeafCell {
-        let mut offset = offset;
-        let mut payload_size: u64 = 0;
-        offset += read_var_int(page, offset, &mut payload_size);
-
-        let payload: Vec<u8> = Vec::from(&page[offset..offset + payload_size as usize]);
-		offset += payload_size as usize;
-
-        let overflow_page: u32 = 0;
-
-        IndexBTreeLeafCell {
-            payload_size,
-            payload,
-            overflow_page,
-        }
+    pub fn new(page: &[u8], offset: usize, usable_size: usize) -> Result<IndexBTreeLeafCell> {
+        CellRef::new(page, offset, PageKind::IndexLeaf, usable_size).map(|c| c.to_index_leaf_cell())
     }
 }
 
@@ -321,22 +525,27 @@ pub struct IndexBTreeInteriorCell {
 }
 
 impl IndexBTreeInteriorCell {
-    pub fn new(page: &[u8], offset: usize) -> IndexBTreeInteriorCell {
-        let mut offset = offset;
-        let left_child_pointer: u32 = read_u32(page,&mut offset);
-
-        let mut payload_size: u64 = 0;
-        offset += read_var_int(page, offset, &mut payload_size);
+    pub fn new(page: &[u8], offset: usize, usable_size: usize) -> Result<IndexBTreeInteriorCell> {
+        CellRef::new(page, offset, PageKind::IndexInterior, usable_size).map(|c| c.to_index_interior_cell())
+    }
+}
 
-        let payload: Vec<u8> = Vec::from(&page[offset..offset + payload_size as usize]);
-        let overflow_page: u32 = 0;
+//######################################################
 
-        IndexBTreeInteriorCell {
-			left_child_pointer,
-            payload_size,
-            payload,
-            overflow_page,
-        }
+/// number of payload bytes stored on the b-tree page itself,
+/// anything beyond that lives in the overflow page chain
+pub fn local_payload_size(payload_size: u64, usable_size: usize, table_leaf: bool) -> usize {
+    let u = usable_size as u64;
+    let max_local = if table_leaf { u - 35 } else { (u - 12) * 64 / 255 - 23 };
+    let min_local = (u - 12) * 32 / 255 - 23;
+    if payload_size <= max_local {
+        return payload_size as usize;
+    }
+    let k = min_local + (payload_size - min_local) % (u - 4);
+    if k <= max_local {
+        k as usize
+    } else {
+        min_local as usize
     }
 }
 
@@ -344,39 +553,76 @@ impl IndexBTreeInteriorCell {
 // record
 //######################################################
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Record {
     pub header_size: u64,
     pub body: Vec<Column>,
 }
 
 impl Record {
-	pub fn new(buf: &[u8]) -> Record {
-        let mut offset = 0;
-        let mut header_size: u64 = 0;
-        let mut row_header: Vec<u64> = vec![];
-        offset += read_var_int(buf, offset, &mut header_size);
-        while offset < header_size as usize {
-            let mut column_serial_type: u64 = 0;
-            offset += read_var_int(buf, offset, &mut column_serial_type);
-            row_header.push(column_serial_type);
+	pub fn new(buf: &[u8], encoding: TextEncoding) -> Result<Record> {
+        RecordView::new(buf, encoding)?.to_record()
+    }
+
+    /// serializes the values with the smallest serial type for each, text in `encoding`.
+    /// The bodiless serial types 8 and 9 for 0 and 1 need `schema_format` 4 or later.
+    /// `header_size` is ignored, the header is rebuilt
+    pub fn to_bytes(&self, encoding: TextEncoding, schema_format: u32) -> Vec<u8> {
+        let mut header = vec![];
+        let mut body = vec![];
+        for column in &self.body {
+            let serial_type = match column {
+                Column::NULL => 0,
+                Column::I64(0) if schema_format >= 4 => 8,
+                Column::I64(1) if schema_format >= 4 => 9,
+                Column::I64(v) => {
+                    let (serial_type, size) = match *v {
+                        -128..=127 => (1, 1),
+                        -32768..=32767 => (2, 2),
+                        -8388608..=8388607 => (3, 3),
+                        -2147483648..=2147483647 => (4, 4),
+                        -140737488355328..=140737488355327 => (5, 6),
+                        _ => (6, 8),
+                    };
+                    body.extend_from_slice(&v.to_be_bytes()[8 - size..]);
+                    serial_type
+                }
+                Column::F64(v) => {
+                    body.extend_from_slice(&v.to_bits().to_be_bytes());
+                    7
+                }
+                Column::Blob(b) => {
+                    body.extend_from_slice(&b.data);
+                    b.data.len() as u64 * 2 + 12
+                }
+                Column::Text(t) => {
+                    let data = encoding.encode(&t.value);
+                    body.extend_from_slice(&data);
+                    data.len() as u64 * 2 + 13
+                }
+            };
+            write_var_int(&mut header, serial_type);
         }
-        let mut body: Vec<Column> = vec![];
-        for entry in row_header {
-            body.push(read_record_column(entry, buf, &mut offset));
+        // the header size counts its own varint, which gets longer for wide records
+        let mut out = vec![];
+        let mut size_len = 1;
+        while write_var_int(&mut out, (header.len() + size_len) as u64) != size_len {
+            size_len = out.len();
+            out.clear();
         }
-        // println!("offset after record {}",offset);
-        Record { header_size, body }
+        out.extend_from_slice(&header);
+        out.extend_from_slice(&body);
+        out
     }
 
     pub fn print(&self) {
         for c in &self.body {
             match c {
                 Column::Blob(b) => {
-                    println!("blob {}", String::from_utf8(b.data.clone()).unwrap())
+                    println!("blob {}", String::from_utf8_lossy(&b.data))
                 }
-                Column::Text(b) => {
-                    println!("blob {}", String::from_utf8(b.data.clone()).unwrap())
+                Column::Text(t) => {
+                    println!("text {}", t.value)
                 }
                 x => {
                     println!("{:?}", x);
@@ -390,236 +636,199 @@ impl Record {
 // column
 //######################################################
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Column {
     NULL,
     I64(i64),
 	F64(f64),
-    False,
-    True,
     Blob(Blob),
     Text(Text),
 }
 
-#[derive(Debug)]
-struct Blob {
+#[derive(Debug, Clone)]
+pub struct Blob {
     pub size: u64,
     pub data: Vec<u8>,
 }
-#[derive(Debug)]
+/// decoded text, `size` is the length in bytes as stored in the file
+#[derive(Debug, Clone)]
 pub struct Text {
     pub size: u64,
-    pub data: Vec<u8>,
+    pub value: String,
 }
 
-pub fn read_record_column(serial_type: u64, buf: &[u8], offset: &mut usize) -> Column {
-    match serial_type {
-        0 => Column::NULL,
-        1 => {
-            let size = 1;
-            if buf.len() < *offset + size {
-                Column::I64(0)
-            } else {
-                let val: i64 = u8::from_be(buf[*offset]) as i64;
-                *offset += size;
-                Column::I64(val)
-            }
-        }
-        2 => {
-            let size = 2;
-            if buf.len() < *offset + size {
-                Column::I64(0)
-            } else {
-                let mut byte_arr: [u8; 2] = [0u8; 2];
-                byte_arr.copy_from_slice(&buf[*offset..*offset + size]);
-                let val: i64 = u16::from_be_bytes(byte_arr) as i64;
-                *offset += size;
-                Column::I64(val)
-            }
-        }
-        3 => {
-            let size = 3;
-            if buf.len() < *offset + size {
-                Column::I64(0)
-            } else {
-                let mut byte_arr: [u8; 4] = [0u8; 4];
-                byte_arr[1..].copy_from_slice(&buf[*offset..*offset + size]);
-                let val: i64 = u32::from_be_bytes(byte_arr) as i64;
-                //TODO: this cast will not result in the correct value
-                *offset += size;
-                Column::I64(val)
-            }
-        }
-        4 => {
-            let size = 4;
-            if buf.len() < *offset + size {
-                Column::I64(0)
-            } else {
-                let mut byte_arr: [u8; 4] = [0u8; 4];
-                byte_arr.copy_from_slice(&buf[*offset..*offset + size]);
-                let val: i64 = u32::from_be_bytes(byte_arr) as i64;
-                *offset += size;
-                Column::I64(val)
-            }
-        }
-        5 => {
-            let size = 6;
-            if buf.len() < *offset + size {
-                Column::I64(0)
-            } else {
-                let mut byte_arr: [u8; 8] = [0u8; 8];
-                byte_arr[2..].copy_from_slice(&buf[*offset..*offset + size]);
-                let val: i64 = u64::from_be_bytes(byte_arr) as i64;
-                //TODO: this cast will not result in the correct value
-                *offset += size;
-                Column::I64(val)
-            }
-        }
-        6 => {
-            let size = 8;
-            if buf.len() < *offset + size {
-                Column::I64(0)
-            } else {
-                let mut byte_arr: [u8; 8] = [0u8; 8];
-                byte_arr.copy_from_slice(&buf[*offset..*offset + size]);
-                let val: i64 = u64::from_be_bytes(byte_arr) as i64;
-                *offset += size;
-                Column::I64(val)
-            }
-        }
-        7 => {
-            let size = 8;
-            if buf.len() < *offset + size {
-                Column::F64(0f64)
-            } else {
-                let mut byte_arr: [u8; 8] = [0u8; 8];
-                byte_arr.copy_from_slice(&buf[*offset..*offset + size]);
-                let val: f64 = f64::from_be_bytes(byte_arr);
-                *offset += size;
-                Column::F64(val)
-            }
-        }
-        8 => Column::False,
-        9 => Column::True,
-        10 => panic!("unexpected record serial type 10"),
-        11 => panic!("unexpected record serial type 11"),
-        x => {
-            if x % 2 == 0 {
-                let size = (x - 12) / 2;
-                *offset += size as usize;
-                Column::Blob(Blob {
-                    size,
-                    data: Vec::from(&buf[*offset - size as usize..*offset]),
-                })
-            } else {
-                let size = (x - 13) / 2;
-                *offset += size as usize;
-                let data = Vec::from(&buf[*offset - size as usize..*offset]);
-                let str_data = String::from_utf8(data).unwrap();
-                // println!("string data {}", str_data);
-                Column::Text(Text {
-                    size,
-                    data: Vec::from(&buf[*offset - size as usize..*offset]),
-                })
-            }
+impl Text {
+    pub fn new(value: &str) -> Text {
+        Text {
+            size: value.len() as u64,
+            value: value.to_string(),
         }
     }
+
+    pub fn as_str(&self) -> &str {
+        &self.value
+    }
 }
 
-impl cmp::Eq for Column {
+pub fn read_record_column(serial_type: u64, buf: &[u8], offset: &mut usize, encoding: TextEncoding) -> Result<Column> {
+    ValueRef::read(serial_type, buf, offset, encoding).map(|v| v.to_column())
+}
 
-} 
+//######################################################
+// comparison
+//######################################################
 
-impl cmp::PartialEq for Column {
-	fn eq(&self, other: &Self) -> bool {
+/// built-in collating sequences, user defined collations can not be loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Collation {
+	#[default]
+	Binary,
+	/// folds ascii letters only, like sqlite
+	NoCase,
+	/// ignores trailing spaces
+	RTrim,
+}
+
+impl Collation {
+	pub fn from_name(name: &str) -> Result<Collation> {
+		match name.to_ascii_uppercase().as_str() {
+			"BINARY" => Ok(Collation::Binary),
+			"NOCASE" => Ok(Collation::NoCase),
+			"RTRIM" => Ok(Collation::RTrim),
+			_ => Err(Error::UnsupportedFeature(format!("collation {}", name))),
+		}
+	}
+
+	/// BINARY follows memcmp on the stored bytes, so its order depends on the encoding.
+	/// sqlite runs the other collations on utf-8
+	pub fn compare(&self, a: &str, b: &str, encoding: TextEncoding) -> Ordering {
 		match self {
-			Column::NULL => {
-				match other {
-					Column::NULL => true,
-					_ => false,
-				}
-			},
-			Column::False => {
-				match other {
-					Column::False => true,
-					_ => false,
-				}
+			Collation::Binary => match encoding {
+				// utf-8 byte order is code point order
+				TextEncoding::Utf8 => a.cmp(b),
+				TextEncoding::Utf16Le => a
+					.encode_utf16()
+					.flat_map(u16::to_le_bytes)
+					.cmp(b.encode_utf16().flat_map(u16::to_le_bytes)),
+				TextEncoding::Utf16Be => a.encode_utf16().cmp(b.encode_utf16()),
 			},
-			Column::True => {
-				match other {
-					Column::True => true,
-					_ => false,
-				}
-			},
-			Column::Text(t1) => {
-				match other {
-					Column::Text(t2) => true, // TODO impl eq for text
-					_ => false,
-				}
-			},
-			Column::Blob(b1) => {
-				match other {
-					Column::Text(b2) => true, // TODO impl eq for Blob
-					_ => false,
-				}
-			},
-			Column::F64(s) => {
-				match other {
-					Column::F64(o) => *s == *o,
-					Column::I64(o) => *s == *o as f64,
-					_ => false,
-				}
-			}
-			Column::I64(s) => {
-				match other {
-					Column::NULL => false,
-					Column::Blob(_) => false,
-					Column::Text(_) => false,
-					Column::True => false,
-					Column::False => false,
-					Column::F64(o) => *s as f64 == *o,
-					Column::I64(o) => *s as i64 == *o,
-				}
-			},
-			// _ => panic!("aaaaa"),
-		}	
+			Collation::NoCase => a
+				.bytes()
+				.map(|c| c.to_ascii_lowercase())
+				.cmp(b.bytes().map(|c| c.to_ascii_lowercase())),
+			Collation::RTrim => a.trim_end_matches(' ').cmp(b.trim_end_matches(' ')),
+		}
 	}
 }
 
-//maybe implement partial only?
-impl cmp::Ord for Column {
-	fn cmp(&self, other: &Column) -> Ordering{
+impl Column {
+	/// sqlite ordering: NULL < INTEGER and REAL < TEXT < BLOB,
+	/// `collation` is only used when both sides are text
+	pub fn compare(&self, other: &Column, collation: Collation) -> Ordering {
+		self.compare_encoded(other, collation, TextEncoding::Utf8)
+	}
+
+	/// like `compare`, with BINARY text ordered as stored in a database using `encoding`
+	pub fn compare_encoded(&self, other: &Column, collation: Collation, encoding: TextEncoding) -> Ordering {
+		let class = self.storage_class().cmp(&other.storage_class());
+		if class != Ordering::Equal {
+			return class;
+		}
+		match (self, other) {
+			(Column::Text(a), Column::Text(b)) => collation.compare(&a.value, &b.value, encoding),
+			(Column::Blob(a), Column::Blob(b)) => a.data.cmp(&b.data),
+			(Column::F64(a), Column::F64(b)) => compare_f64(*a, *b),
+			(Column::F64(a), b) => compare_i64_f64(b.as_i64(), *a).reverse(),
+			(a, Column::F64(b)) => compare_i64_f64(a.as_i64(), *b),
+			(a, b) => a.as_i64().cmp(&b.as_i64()),
+		}
+	}
+
+	fn storage_class(&self) -> u8 {
 		match self {
-			Column::NULL => {
-				match other {
-					Column::NULL => Ordering::Equal,
-					_ => Ordering::Less,
-				}
-			},
-			Column::True => {
-				panic!("no ordering for true")
-			},
-			Column::False => {
-				panic!("no ordering for false")
-			},
-			Column::Text(s) => {
-				panic!("not implemnted yet: ordering text")
-			},
-			Column::Blob(s) => {
-				panic!("not implemnted yet: ordering blob")
-			},
-			s => {
-				match other {
-					Column::True => panic!("no ordering for true right side"),
-					Column::False => panic!("no ordering for false right side"),
-					Column::NULL => Ordering::Greater,
-					Column::F64(o) => force_cast_column_to_f64(s).partial_cmp(o).unwrap(),
-					Column::Text(_) => Ordering::Less,
-					Column::Blob(_) => Ordering::Less,
-					o => force_cast_column_to_i64(s).cmp(&force_cast_column_to_i64(o)),
-				}
-			},
+			Column::NULL => 0,
+			Column::I64(_) | Column::F64(_) => 1,
+			Column::Text(_) => 2,
+			Column::Blob(_) => 3,
+		}
+	}
+
+	/// only called on integer columns
+	fn as_i64(&self) -> i64 {
+		match self {
+			Column::I64(v) => *v,
+			_ => 0,
+		}
+	}
+}
+
+/// NaN sorts before every other real
+fn compare_f64(a: f64, b: f64) -> Ordering {
+	match a.partial_cmp(&b) {
+		Some(o) => o,
+		None => a.is_nan().cmp(&b.is_nan()).reverse(),
+	}
+}
+
+/// exact comparison, converting `i` to a double would lose precision above 2^53
+fn compare_i64_f64(i: i64, r: f64) -> Ordering {
+	if r.is_nan() {
+		return Ordering::Greater;
+	}
+	// -2^63 and 2^63, both exactly representable
+	if r < -9223372036854775808.0 {
+		return Ordering::Greater;
+	}
+	if r >= 9223372036854775808.0 {
+		return Ordering::Less;
+	}
+	// in range, truncation toward zero is exact
+	let truncated = r as i64;
+	match i.cmp(&truncated) {
+		Ordering::Equal => compare_f64(truncated as f64, r),
+		o => o,
+	}
+}
+
+/// sort direction and collation of every column of an index key,
+/// columns past the end (like the trailing rowid) sort ascending with BINARY
+#[derive(Debug, Clone, Default)]
+pub struct KeyOrder {
+	pub columns: Vec<(SortOrder, Collation)>,
+	pub encoding: TextEncoding,
+}
+
+impl KeyOrder {
+	/// compares the leading columns of an index entry with a key of any length,
+	/// an entry that matches the whole key compares equal
+	pub fn compare_prefix(&self, entry: &[Column], key: &[Column]) -> Ordering {
+		for (i, k) in key.iter().enumerate() {
+			let e = match entry.get(i) {
+				Some(e) => e,
+				None => return Ordering::Less,
+			};
+			let (order, collation) = self.columns.get(i).copied().unwrap_or((SortOrder::Asc, Collation::Binary));
+			let ordering = e.compare_encoded(k, collation, self.encoding);
+			let ordering = if order == SortOrder::Desc { ordering.reverse() } else { ordering };
+			if ordering != Ordering::Equal {
+				return ordering;
+			}
 		}
+		Ordering::Equal
+	}
+}
+
+impl cmp::Eq for Column {}
+
+impl cmp::PartialEq for Column {
+	fn eq(&self, other: &Self) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+
+impl cmp::Ord for Column {
+	fn cmp(&self, other: &Column) -> Ordering {
+		self.compare(other, Collation::Binary)
 	}
 }
 
@@ -629,16 +838,274 @@ impl cmp::PartialOrd for Column {
 	}
 }
 
-fn force_cast_column_to_i64(c: &Column) -> i64{
-	match *c {
-		Column::I64(v)	=> v as i64,
-		_ => panic!("can not cast to int"),
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tools::helper::read_var_int;
+
+	fn blob(data: &[u8]) -> Column {
+		Column::Blob(Blob {
+			size: data.len() as u64,
+			data: data.to_vec(),
+		})
 	}
-}
 
-fn force_cast_column_to_f64(c: &Column) -> f64{
-	match *c {
-		Column::I64(v)	=> v as f64,
-		_ => panic!("can not cast to float"),
+	/// serial types from the record header
+	fn serial_types(bytes: &[u8]) -> Vec<u64> {
+		let mut header_size = 0;
+		let mut offset = read_var_int(bytes, 0, &mut header_size).unwrap();
+		let mut types = vec![];
+		while offset < header_size as usize {
+			let mut serial_type = 0;
+			offset += read_var_int(bytes, offset, &mut serial_type).unwrap();
+			types.push(serial_type);
+		}
+		types
+	}
+
+	fn round_trip(columns: Vec<Column>, encoding: TextEncoding, schema_format: u32) -> Vec<u64> {
+		let record = Record { header_size: 0, body: columns };
+		let bytes = record.to_bytes(encoding, schema_format);
+		let read = Record::new(&bytes, encoding).unwrap();
+		assert_eq!(read.body.len(), record.body.len());
+		for (a, b) in read.body.iter().zip(&record.body) {
+			// equality alone would let 1 and 1.0 through
+			assert_eq!(std::mem::discriminant(a), std::mem::discriminant(b), "{:?} read as {:?}", b, a);
+			assert_eq!(a, b);
+		}
+		serial_types(&bytes)
+	}
+
+	#[test]
+	fn record_integers_use_the_smallest_serial_type() {
+		let values: [(i64, u64); 17] = [
+			(0, 8),
+			(1, 9),
+			(2, 1),
+			(-1, 1),
+			(127, 1),
+			(-128, 1),
+			(128, 2),
+			(-32768, 2),
+			(32768, 3),
+			(-8388608, 3),
+			(8388608, 4),
+			(i32::MIN as i64, 4),
+			(i32::MAX as i64 + 1, 5),
+			(-140737488355328, 5),
+			(140737488355328, 6),
+			(i64::MIN, 6),
+			(i64::MAX, 6),
+		];
+		let columns = values.iter().map(|&(v, _)| Column::I64(v)).collect();
+		let expected: Vec<u64> = values.iter().map(|&(_, t)| t).collect();
+		assert_eq!(round_trip(columns, TextEncoding::Utf8, 4), expected);
+	}
+
+	#[test]
+	fn record_zero_and_one_before_schema_format_4() {
+		let columns = vec![Column::I64(0), Column::I64(1)];
+		assert_eq!(round_trip(columns, TextEncoding::Utf8, 1), vec![1, 1]);
+	}
+
+	#[test]
+	fn record_null_real_text_and_blob() {
+		let columns = vec![
+			Column::NULL,
+			Column::F64(-0.5),
+			Column::F64(1.0),
+			Column::Text(Text::new("")),
+			Column::Text(Text::new("héllo")),
+			blob(&[]),
+			blob(&[0, 1, 2, 0xff]),
+		];
+		assert_eq!(round_trip(columns, TextEncoding::Utf8, 4), vec![0, 7, 7, 13, 25, 12, 20]);
+	}
+
+	#[test]
+	fn record_text_in_utf16() {
+		for encoding in [TextEncoding::Utf16Le, TextEncoding::Utf16Be] {
+			let columns = vec![Column::Text(Text::new("héllo \u{1f600}"))];
+			// 8 utf-16 code units
+			assert_eq!(round_trip(columns, encoding, 4), vec![16 * 2 + 13]);
+		}
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn record_header_size_with_a_two_byte_varint() {
+		// 126 one byte serial types and the size itself just fit in 127 bytes, one more does not
+		for (count, header_size) in [(126, 127), (127, 129)] {
+			let columns = vec![Column::NULL; count];
+			let bytes = Record { header_size: 0, body: columns }.to_bytes(TextEncoding::Utf8, 4);
+			let mut size = 0;
+			read_var_int(&bytes, 0, &mut size).unwrap();
+			assert_eq!(size, header_size);
+			assert_eq!(Record::new(&bytes, TextEncoding::Utf8).unwrap().body.len(), count);
+		}
+	}
+	/// a header with a different value in every field
+	fn header_bytes() -> [u8; HEADER_SIZE] {
+		let mut buf = crate::tools::testing::header_bytes(4096, 10);
+		buf[18] = 2;
+		buf[19] = 2;
+		buf[20] = 8;
+		for (i, offset) in (24..72).step_by(4).enumerate() {
+			write_u32(&mut buf, offset, 100 + i as u32);
+		}
+		write_u32(&mut buf, 44, 3);
+		write_u32(&mut buf, 56, 2);
+		write_u32(&mut buf, 92, 100);
+		buf
+	}
+
+	#[test]
+	fn header_round_trip() {
+		let mut buf = header_bytes();
+		// reserved for expansion, newer versions may use them
+		buf[72..92].fill(0xaa);
+		let header = SqliteHeader::new(&buf).unwrap();
+		assert_eq!((header.page_size(), header.usable_size()), (4096, 4088));
+		assert_eq!((header.file_format_write_version, header.file_format_read_version), (2, 2));
+		assert_eq!((header.file_change_counter, header.database_size), (100, 101));
+		assert_eq!((header.first_freelist_trunk_page, header.freelist_page_count), (102, 103));
+		assert_eq!((header.schema_cookie, header.schema_format, header.default_cache_size), (104, 3, 106));
+		assert_eq!((header.largest_root_page, header.user_version, header.incremental_vacuum), (107, 109, 110));
+		assert_eq!(header.text_encoding, TextEncoding::Utf16Le);
+		assert_eq!((header.application_id, header.version_valid_for, header.version), (111, 100, 3_045_000));
+		assert!(header.database_size_valid());
+
+		let bytes = header.to_bytes();
+		assert_eq!(bytes[..72], buf[..72]);
+		assert_eq!(bytes[92..], buf[92..]);
+
+		// 65536 is stored as 1
+		let mut buf = crate::tools::testing::header_bytes(65536, 1);
+		let header = SqliteHeader::new(&buf).unwrap();
+		assert_eq!((header.page_size, header.page_size()), (1, 65536));
+		assert_eq!(header.to_bytes(), buf);
+		write_u32(&mut buf, 92, 0);
+		assert!(!SqliteHeader::new(&buf).unwrap().database_size_valid());
+	}
+
+	#[test]
+	fn header_validation_errors() {
+		// (change to a valid header, true for corrupt and false for unsupported)
+		type Change = fn(&mut [u8; HEADER_SIZE]);
+		let cases: [(&str, Change, bool); 11] = [
+			("header string", |b| b[0] = b's', true),
+			("text encoding", |b| write_u32(b, 56, 4), true),
+			("page size not a power of two", |b| write_u16(b, 16, 1000), true),
+			("page size below 512", |b| write_u16(b, 16, 256), true),
+			("write version", |b| b[18] = 3, false),
+			("read version", |b| b[19] = 0, false),
+			("max payload fraction", |b| b[21] = 65, true),
+			("leaf payload fraction", |b| b[23] = 0, true),
+			("fewer than 480 usable bytes", |b| {
+				write_u16(b, 16, 512);
+				b[20] = 33;
+			}, true),
+			("schema format", |b| write_u32(b, 44, 5), false),
+			("incremental vacuum without auto-vacuum", |b| {
+				write_u32(b, 52, 0);
+				write_u32(b, 64, 1);
+			}, true),
+		];
+		for (name, change, corrupt) in cases {
+			let mut buf = header_bytes();
+			change(&mut buf);
+			match SqliteHeader::new(&buf) {
+				Err(Error::Corrupt { page: 1, .. }) if corrupt => {}
+				Err(Error::UnsupportedFeature(_)) if !corrupt => {}
+				other => panic!("{}: {:?}", name, other.map(|_| ())),
+			}
+		}
+		// the last ones that are still valid
+		let mut buf = header_bytes();
+		write_u16(&mut buf, 16, 512);
+		buf[20] = 32;
+		write_u32(&mut buf, 44, 4);
+		assert!(SqliteHeader::new(&buf).is_ok());
+	}
+	fn text(s: &str) -> Column {
+		Column::Text(Text::new(s))
+	}
+
+	/// every expected order below is what sqlite answers for the same comparison
+	#[test]
+	fn columns_order_null_numbers_text_blobs() {
+		let sorted = [
+			Column::NULL,
+			Column::I64(-5),
+			Column::F64(-4.5),
+			Column::I64(0),
+			Column::F64(0.5),
+			Column::I64(1),
+			text(""),
+			text("a"),
+			blob(b""),
+			blob(b"\0"),
+		];
+		for (i, a) in sorted.iter().enumerate() {
+			for (j, b) in sorted.iter().enumerate() {
+				assert_eq!(a.compare(b, Collation::Binary), i.cmp(&j), "{:?} and {:?}", a, b);
+			}
+		}
+		// the collation only applies between texts
+		assert_eq!(text("A").compare(&blob(b"a"), Collation::NoCase), Ordering::Less);
+	}
+
+	#[test]
+	fn integers_compare_exactly_with_reals() {
+		let cases = [
+			// 2^53 + 1 becomes 2^53 as a double
+			(9_007_199_254_740_993, 9_007_199_254_740_992.0, Ordering::Greater),
+			(9_007_199_254_740_992, 9_007_199_254_740_992.0, Ordering::Equal),
+			(i64::MAX, 9_223_372_036_854_775_808.0, Ordering::Less),
+			(i64::MIN, -9_223_372_036_854_775_808.0, Ordering::Equal),
+			(i64::MIN, -9.3e18, Ordering::Greater),
+			(i64::MAX, 1e300, Ordering::Less),
+			(3, 3.5, Ordering::Less),
+			(-3, -3.5, Ordering::Greater),
+			(0, -0.0, Ordering::Equal),
+		];
+		for (i, r, expected) in cases {
+			assert_eq!(Column::I64(i).compare(&Column::F64(r), Collation::Binary), expected, "{} and {}", i, r);
+			assert_eq!(Column::F64(r).compare(&Column::I64(i), Collation::Binary), expected.reverse(), "{} and {}", r, i);
+		}
+	}
+
+	#[test]
+	fn collations() {
+		let cases = [
+			(Collation::NoCase, "abc", "ABC", Ordering::Equal),
+			// only ascii letters fold
+			(Collation::NoCase, "É", "é", Ordering::Less),
+			(Collation::NoCase, "Éa", "ÉA", Ordering::Equal),
+			(Collation::NoCase, "z", "É", Ordering::Less),
+			// folded to lower case, '[' sits between the upper and lower case letters
+			(Collation::NoCase, "[", "A", Ordering::Less),
+			(Collation::Binary, "[", "A", Ordering::Greater),
+			(Collation::RTrim, "a", "a   ", Ordering::Equal),
+			(Collation::RTrim, "", "   ", Ordering::Equal),
+			(Collation::RTrim, "a ", "a\t", Ordering::Less),
+			(Collation::RTrim, " a", "a", Ordering::Less),
+			(Collation::RTrim, "a  b", "a b", Ordering::Less),
+			(Collation::Binary, "a", "a ", Ordering::Less),
+		];
+		for (collation, a, b, expected) in cases {
+			assert_eq!(text(a).compare(&text(b), collation), expected, "{:?} {:?} {:?}", collation, a, b);
+			assert_eq!(collation.compare(b, a, TextEncoding::Utf8), expected.reverse(), "{:?} {:?} {:?}", collation, b, a);
+		}
+	}
+
+	#[test]
+	fn binary_text_follows_the_stored_bytes() {
+		// U+0100 is 0x01 0x00 in UTF-16LE, which sorts before 'a' as 0x61 0x00
+		let (a, b) = (text("a"), text("\u{100}"));
+		assert_eq!(a.compare_encoded(&b, Collation::Binary, TextEncoding::Utf8), Ordering::Less);
+		assert_eq!(a.compare_encoded(&b, Collation::Binary, TextEncoding::Utf16Be), Ordering::Less);
+		assert_eq!(a.compare_encoded(&b, Collation::Binary, TextEncoding::Utf16Le), Ordering::Greater);
+		// NOCASE runs on utf-8 whatever the encoding
+		assert_eq!(a.compare_encoded(&b, Collation::NoCase, TextEncoding::Utf16Le), Ordering::Less);
+	}
+}
@@ -0,0 +1,69 @@
+use std::fmt;
+
+//######################################################
+// error
+//######################################################
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    /// the file does not follow the sqlite format, `page` is 0 when the
+    /// problem is not tied to a specific page
+    Corrupt { page: u32, reason: String },
+    UnsupportedFeature(String),
+    TypeMismatch(String),
+    NotFound(String),
+    /// a write would break a rule of the schema, like a duplicate rowid
+    Constraint(String),
+    /// another connection holds a lock that is in the way
+    Busy(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn corrupt<S: Into<String>>(reason: S) -> Error {
+        Error::Corrupt {
+            page: 0,
+            reason: reason.into(),
+        }
+    }
+
+    /// attaches a page number to corruption errors raised while parsing that page
+    pub fn on_page(self, page: u32) -> Error {
+        match self {
+            Error::Corrupt { page: 0, reason } => Error::Corrupt { page, reason },
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Corrupt { page: 0, reason } => write!(f, "database corrupt: {}", reason),
+            Error::Corrupt { page, reason } => write!(f, "database corrupt on page {}: {}", page, reason),
+            Error::UnsupportedFeature(s) => write!(f, "unsupported feature: {}", s),
+            Error::TypeMismatch(s) => write!(f, "type mismatch: {}", s),
+            Error::NotFound(s) => write!(f, "not found: {}", s),
+            Error::Constraint(s) => write!(f, "constraint failed: {}", s),
+            Error::Busy(s) => write!(f, "database is locked: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,109 @@
+use crate::tools::{
+    error::{Error, Result},
+    helper::read_u32,
+    pager::Pager,
+};
+use std::collections::HashSet;
+
+//######################################################
+// freelist
+//######################################################
+
+/// unused pages, kept as a chain of trunk pages that each list a number of leaf pages.
+/// Trunk pages are free pages as well.
+#[derive(Debug, Default)]
+pub struct Freelist {
+    pub trunk_pages: Vec<u32>,
+    pub leaf_pages: Vec<u32>,
+}
+
+impl Freelist {
+    /// every free page, sorted
+    pub fn pages(&self) -> Vec<u32> {
+        let mut pages: Vec<u32> = self.trunk_pages.iter().chain(&self.leaf_pages).copied().collect();
+        pages.sort_unstable();
+        pages
+    }
+
+    pub fn len(&self) -> usize {
+        self.trunk_pages.len() + self.leaf_pages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FreelistStats {
+    pub free_pages: u32,
+    pub total_pages: u32,
+    /// share of the file taken by free pages, 0 to 100
+    pub percent_free: f64,
+}
+
+/// walks the trunk chain starting at the header's first trunk page and
+/// checks the result against the header's free page count
+pub fn read_freelist(pager: &mut Pager) -> Result<Freelist> {
+    let page_count = pager.page_count()?;
+    let expected = pager.header().freelist_page_count;
+    // a trunk page holds the next trunk pointer, a count and the leaf page numbers
+    let max_leaves = pager.header().usable_size() / 4 - 2;
+    let mut freelist = Freelist::default();
+    let mut seen: HashSet<u32> = HashSet::new();
+    let mut check = |page: u32, from: u32| {
+        if page == 0 || page > page_count || !seen.insert(page) {
+            return Err(Error::Corrupt {
+                page: from,
+                reason: format!("invalid or repeated freelist page {}", page),
+            });
+        }
+        Ok(())
+    };
+
+    let mut trunk = pager.header().first_freelist_trunk_page;
+    while trunk != 0 {
+        check(trunk, 1)?;
+        let page = pager.read_raw_page(trunk as usize)?;
+        let next = read_u32(&page, &mut 0)?;
+        let leaf_count = read_u32(&page, &mut 4)? as usize;
+        if leaf_count > max_leaves {
+            return Err(Error::Corrupt {
+                page: trunk,
+                reason: format!("freelist trunk lists {} leaf pages", leaf_count),
+            });
+        }
+        freelist.trunk_pages.push(trunk);
+        for i in 0..leaf_count {
+            let leaf = read_u32(&page, &mut (8 + i * 4))?;
+            check(leaf, trunk)?;
+            freelist.leaf_pages.push(leaf);
+        }
+        if freelist.len() > expected as usize {
+            break;
+        }
+        trunk = next;
+    }
+    if freelist.len() != expected as usize {
+        return Err(Error::Corrupt {
+            page: 1,
+            reason: format!("header counts {} free pages, freelist holds {}", expected, freelist.len()),
+        });
+    }
+    Ok(freelist)
+}
+
+pub fn freelist_stats(pager: &mut Pager) -> Result<FreelistStats> {
+    let free_pages = read_freelist(pager)?.len() as u32;
+    let total_pages = pager.page_count()?;
+    let percent_free = if total_pages == 0 {
+        0.0
+    } else {
+        free_pages as f64 * 100.0 / total_pages as f64
+    };
+    Ok(FreelistStats {
+        free_pages,
+        total_pages,
+        percent_free,
+    })
+}
@@ -1,17 +1,32 @@
 // pub mod helper;
 
-pub fn read_u16(buf: &[u8], offset:&mut  usize) -> u16 {
+use crate::tools::error::{Error, Result};
+
+fn read_bytes(buf: &[u8], offset: usize, len: usize) -> Result<&[u8]> {
+    buf.get(offset..offset + len)
+        .ok_or_else(|| Error::corrupt(format!("read of {} bytes at offset {} past end of buffer", len, offset)))
+}
+
+pub fn read_u16(buf: &[u8], offset:&mut  usize) -> Result<u16> {
     let mut byte_arr: [u8; 2] = [0u8; 2];
+    byte_arr.copy_from_slice(read_bytes(buf, *offset, 2)?);
 	*offset+=2;
-    byte_arr.copy_from_slice(&buf[*offset-2..*offset]);
-    u16::from_be_bytes(byte_arr)
+    Ok(u16::from_be_bytes(byte_arr))
 }
 
-pub fn read_u32(buf: &[u8],offset: &mut usize) -> u32 {
+pub fn read_u32(buf: &[u8],offset: &mut usize) -> Result<u32> {
     let mut byte_arr: [u8; 4] = [0u8; 4];
+    byte_arr.copy_from_slice(read_bytes(buf, *offset, 4)?);
 	*offset+=4;
-    byte_arr.copy_from_slice(&buf[*offset-4..*offset]);
-    u32::from_be_bytes(byte_arr)
+    Ok(u32::from_be_bytes(byte_arr))
+}
+
+pub fn write_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+pub fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
 }
 
 
@@ -22,7 +37,7 @@ pub fn read_u32(buf: &[u8],offset: &mut usize) -> u32 {
 // }
 
 
-pub fn read_var_int(buf: &[u8], offset: usize, result: &mut u64) -> usize{
+pub fn read_var_int(buf: &[u8], offset: usize, result: &mut u64) -> Result<usize> {
 	let mask: u8 = 0b10000000;
 	let mask2: u8 = 0b01111111;
 	let max_len = 9;
@@ -31,14 +46,108 @@ pub fn read_var_int(buf: &[u8], offset: usize, result: &mut u64) -> usize{
 	let mut next_exists = true;
 	let mut i = 0;
 	while next_exists && i < max_len {
-		if i > 0 {
-			*result = *result << 7;
+		let byte = *buf
+			.get(offset + i)
+			.ok_or_else(|| Error::corrupt(format!("varint at offset {} runs past end of buffer", offset)))?;
+		// the ninth byte carries all 8 bits
+		if i == max_len - 1 {
+			*result <<= 8;
+		} else if i > 0 {
+			*result <<= 7;
 		}
-		let current = if i == max_len - 1 { buf[offset+i] } else { buf[offset+i] & mask2 };
-		*result = *result | (current as u64); 
-		next_exists = mask & buf[offset+i] == mask;
+		let current = if i == max_len - 1 { byte } else { byte & mask2 };
+		*result |= current as u64;
+		next_exists = mask & byte == mask;
 		i += 1;
 	}
-	i
+	Ok(i)
+}
+
+/// appends `value` as a varint, the inverse of `read_var_int`. Returns the number of bytes written
+pub fn write_var_int(buf: &mut Vec<u8>, value: u64) -> usize {
+	if value > 0x00ff_ffff_ffff_ffff {
+		let mut bytes = [0u8; 9];
+		bytes[8] = value as u8;
+		let mut v = value >> 8;
+		for b in bytes[..8].iter_mut().rev() {
+			*b = (v & 0x7f) as u8 | 0x80;
+			v >>= 7;
+		}
+		buf.extend_from_slice(&bytes);
+		return bytes.len();
+	}
+	let mut bytes = [0u8; 9];
+	let mut len = 0;
+	let mut v = value;
+	loop {
+		bytes[len] = (v & 0x7f) as u8 | 0x80;
+		len += 1;
+		v >>= 7;
+		if v == 0 {
+			break;
+		}
+	}
+	bytes[0] &= 0x7f;
+	bytes[..len].reverse();
+	buf.extend_from_slice(&bytes[..len]);
+	len
 }
 
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn round_trip(value: u64) -> usize {
+		let mut buf = vec![0xaa];
+		let len = write_var_int(&mut buf, value);
+		assert_eq!(buf.len(), 1 + len);
+		let mut result = 0;
+		assert_eq!(read_var_int(&buf, 1, &mut result).unwrap(), len);
+		assert_eq!(result, value, "varint {:x?}", &buf[1..]);
+		len
+	}
+
+	#[test]
+	fn var_int_lengths_at_the_boundaries() {
+		let cases: [(u64, usize); 12] = [
+			(0, 1),
+			(0x7f, 1),
+			(0x80, 2),
+			(0x3fff, 2),
+			(0x4000, 3),
+			(0x001f_ffff, 3),
+			(0x0020_0000, 4),
+			(0x00ff_ffff_ffff_ffff, 8),
+			(0x0100_0000_0000_0000, 9),
+			(0x7fff_ffff_ffff_ffff, 9),
+			(0x8000_0000_0000_0000, 9),
+			(u64::MAX, 9),
+		];
+		for (value, len) in cases {
+			assert_eq!(round_trip(value), len, "value {:#x}", value);
+		}
+	}
+
+	#[test]
+	fn var_int_negative_values() {
+		for value in [-1i64, -2, -128, -129, i32::MIN as i64, i64::MIN, i64::MIN + 1] {
+			assert_eq!(round_trip(value as u64), 9, "value {}", value);
+		}
+	}
+
+	#[test]
+	fn var_int_ninth_byte_keeps_all_bits() {
+		let mut buf = vec![0xff; 9];
+		buf[8] = 0x80;
+		let mut result = 0;
+		assert_eq!(read_var_int(&buf, 0, &mut result).unwrap(), 9);
+		assert_eq!(result, 0xffff_ffff_ffff_ff80);
+	}
+
+	#[test]
+	fn var_int_past_the_end() {
+		let mut result = 0;
+		assert!(read_var_int(&[0x81, 0x82], 0, &mut result).is_err());
+		assert!(read_var_int(&[], 0, &mut result).is_err());
+	}
+}
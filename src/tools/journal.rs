@@ -0,0 +1,321 @@
+use crate::tools::{
+    error::{Error, Result},
+    helper::{read_u32, write_u32},
+};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const JOURNAL_MAGIC: [u8; 8] = [0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+const JOURNAL_HEADER_SIZE: usize = 28;
+/// smallest sector size sqlite assumes, the header is padded to it
+const JOURNAL_SECTOR_SIZE: usize = 512;
+
+//######################################################
+// rollback journal
+//######################################################
+
+/// original content of the pages changed by a transaction that never finished
+pub struct Journal {
+    /// size of the database in pages before the transaction started
+    pub initial_size: u32,
+    pub page_size: usize,
+    /// page number to original image, only the first record of a page counts
+    pub pages: HashMap<u32, Vec<u8>>,
+}
+
+impl Journal {
+    /// parses the journal at `path` when it is hot, that is it exists, has a valid
+    /// header and does not belong to a transaction a super-journal already committed.
+    /// Records are read up to the first one with a bad checksum, like sqlite does.
+    /// The caller still has to make sure no writer holds the RESERVED lock.
+    pub fn read_hot<P: AsRef<Path>>(path: P) -> Result<Option<Journal>> {
+        let path = path.as_ref();
+        let data = match fs::read(path) {
+            Ok(d) => d,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        // persist and truncate journal modes leave a zeroed or empty file behind
+        if data.len() < JOURNAL_HEADER_SIZE || data[..8] != JOURNAL_MAGIC {
+            return Ok(None);
+        }
+        if let Some(name) = super_journal_name(&data) {
+            let super_journal = path.parent().unwrap_or(Path::new("")).join(name);
+            if !super_journal.exists() {
+                return Ok(None);
+            }
+        }
+
+        let mut journal = Journal {
+            initial_size: read_u32(&data, &mut 16)?,
+            page_size: read_u32(&data, &mut 24)? as usize,
+            pages: HashMap::new(),
+        };
+        let mut offset = 0;
+        // a journal holds one or more segments, each starting with a header on a sector boundary
+        while offset + JOURNAL_HEADER_SIZE <= data.len() && data[offset..offset + 8] == JOURNAL_MAGIC {
+            let record_count = read_u32(&data, &mut (offset + 8))?;
+            let nonce = read_u32(&data, &mut (offset + 12))?;
+            let sector_size = read_u32(&data, &mut (offset + 20))? as usize;
+            let page_size = read_u32(&data, &mut (offset + 24))? as usize;
+            if !(32..=65536).contains(&sector_size) || !sector_size.is_power_of_two() {
+                return Err(journal_corrupt(format!("invalid sector size {}", sector_size)));
+            }
+            if page_size != journal.page_size || !(512..=65536).contains(&page_size) || !page_size.is_power_of_two() {
+                return Err(journal_corrupt(format!("invalid page size {}", page_size)));
+            }
+            let record_size = 4 + page_size + 4;
+            offset += sector_size;
+            // 0xffffffff means the count was never written and the records run to the end of the file
+            let record_count = if record_count == u32::MAX {
+                data.len().saturating_sub(offset) / record_size
+            } else {
+                record_count as usize
+            };
+            for _ in 0..record_count {
+                let record = match data.get(offset..offset + record_size) {
+                    Some(r) => r,
+                    None => return Ok(Some(journal)),
+                };
+                let page_number = read_u32(record, &mut 0)?;
+                let page = &record[4..4 + page_size];
+                if read_u32(record, &mut (4 + page_size))? != page_checksum(nonce, page) {
+                    return Ok(Some(journal));
+                }
+                if page_number != 0 && page_number <= journal.initial_size {
+                    journal.pages.entry(page_number).or_insert_with(|| page.to_vec());
+                }
+                offset += record_size;
+            }
+            offset = offset.div_ceil(sector_size) * sector_size;
+        }
+        Ok(Some(journal))
+    }
+
+    /// writes a single segment journal at `path` the way sqlite does in DELETE mode,
+    /// and syncs it so it is on disk before the database file is touched
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let nonce = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        let mut data = vec![0u8; JOURNAL_SECTOR_SIZE];
+        data[..8].copy_from_slice(&JOURNAL_MAGIC);
+        write_u32(&mut data, 8, self.pages.len() as u32);
+        write_u32(&mut data, 12, nonce);
+        write_u32(&mut data, 16, self.initial_size);
+        write_u32(&mut data, 20, JOURNAL_SECTOR_SIZE as u32);
+        write_u32(&mut data, 24, self.page_size as u32);
+        let mut pages: Vec<(&u32, &Vec<u8>)> = self.pages.iter().collect();
+        pages.sort_unstable_by_key(|(page, _)| **page);
+        for (page, image) in pages {
+            data.extend_from_slice(&page.to_be_bytes());
+            data.extend_from_slice(image);
+            data.extend_from_slice(&page_checksum(nonce, image).to_be_bytes());
+        }
+        let mut file = File::create(path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+/// nonce plus every 200th byte counted back from the end of the page
+fn page_checksum(nonce: u32, page: &[u8]) -> u32 {
+    let mut checksum = nonce;
+    let mut i = page.len() as isize - 200;
+    while i > 0 {
+        checksum = checksum.wrapping_add(page[i as usize] as u32);
+        i -= 200;
+    }
+    checksum
+}
+
+/// name of the super-journal recorded at the end of a journal that is part of
+/// a multi-database transaction: name, its length, its checksum and the magic
+fn super_journal_name(data: &[u8]) -> Option<String> {
+    let end = data.len();
+    if end < 16 + 4 || data[end - 8..] != JOURNAL_MAGIC {
+        return None;
+    }
+    let len = read_u32(data, &mut (end - 16)).ok()? as usize;
+    let checksum = read_u32(data, &mut (end - 12)).ok()?;
+    let name = data.get(end.checked_sub(16 + len)?..end - 16)?;
+    let sum = name.iter().fold(0u32, |s, &b| s.wrapping_add(b as u32));
+    if len == 0 || sum != checksum {
+        return None;
+    }
+    Some(String::from_utf8_lossy(name).into_owned())
+}
+
+fn journal_corrupt(reason: String) -> Error {
+    Error::corrupt(format!("rollback journal: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::{
+        database::Database,
+        defs::{Column, Record, Text},
+        pager::DatabaseOptions,
+        testing::{writable, TempDb},
+    };
+
+    const PAGE_SIZE: usize = 512;
+
+    fn journal(initial_size: u32, pages: &[u32]) -> Journal {
+        Journal {
+            initial_size,
+            page_size: PAGE_SIZE,
+            pages: pages.iter().map(|&p| (p, vec![p as u8; PAGE_SIZE])).collect(),
+        }
+    }
+
+    /// offset of the page image in record `index` of a journal written by `Journal::write`
+    fn record_offset(index: usize) -> usize {
+        JOURNAL_SECTOR_SIZE + index * (4 + PAGE_SIZE + 4) + 4
+    }
+
+    fn sorted_pages(journal: &Journal) -> Vec<(u32, u8)> {
+        let mut pages: Vec<(u32, u8)> = journal.pages.iter().map(|(p, data)| (*p, data[0])).collect();
+        pages.sort_unstable();
+        pages
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let temp = TempDb::create("journal-round-trip", PAGE_SIZE, &[]);
+        let path = temp.sibling("-journal");
+        journal(5, &[1, 3, 4]).write(&path).unwrap();
+        let read = Journal::read_hot(&path).unwrap().unwrap();
+        assert_eq!(read.initial_size, 5);
+        assert_eq!(read.page_size, PAGE_SIZE);
+        assert_eq!(sorted_pages(&read), vec![(1, 1), (3, 3), (4, 4)]);
+    }
+
+    #[test]
+    fn records_end_at_a_bad_checksum() {
+        let temp = TempDb::create("journal-checksum", PAGE_SIZE, &[]);
+        let path = temp.sibling("-journal");
+        journal(5, &[2, 3, 4]).write(&path).unwrap();
+        let mut data = fs::read(&path).unwrap();
+        // one of the bytes the checksum samples, every 200th counted from the end
+        data[record_offset(1) + PAGE_SIZE - 200] ^= 1;
+        fs::write(&path, &data).unwrap();
+        let read = Journal::read_hot(&path).unwrap().unwrap();
+        assert_eq!(sorted_pages(&read), vec![(2, 2)]);
+
+        // a record cut short by a crash ends the journal as well
+        journal(5, &[2, 3, 4]).write(&path).unwrap();
+        let data = fs::read(&path).unwrap();
+        fs::write(&path, &data[..data.len() - 10]).unwrap();
+        let read = Journal::read_hot(&path).unwrap().unwrap();
+        assert_eq!(sorted_pages(&read), vec![(2, 2), (3, 3)]);
+    }
+
+    #[test]
+    fn unknown_record_count_reads_to_the_end() {
+        let temp = TempDb::create("journal-count", PAGE_SIZE, &[]);
+        let path = temp.sibling("-journal");
+        journal(3, &[2, 3, 4]).write(&path).unwrap();
+        let mut data = fs::read(&path).unwrap();
+        write_u32(&mut data, 8, u32::MAX);
+        // a later record of page 2, only the first image of a page is the original
+        let nonce = read_u32(&data, &mut 12).unwrap();
+        let image = vec![9u8; PAGE_SIZE];
+        data.extend_from_slice(&2u32.to_be_bytes());
+        data.extend_from_slice(&image);
+        data.extend_from_slice(&page_checksum(nonce, &image).to_be_bytes());
+        fs::write(&path, &data).unwrap();
+        let read = Journal::read_hot(&path).unwrap().unwrap();
+        // page 4 was added by the transaction, it is cut off rather than restored
+        assert_eq!(sorted_pages(&read), vec![(2, 2), (3, 3)]);
+    }
+
+    #[test]
+    fn cold_journals_are_ignored() {
+        let temp = TempDb::create("journal-cold", PAGE_SIZE, &[]);
+        let path = temp.sibling("-journal");
+        assert!(Journal::read_hot(&path).unwrap().is_none());
+        // what the persist and truncate journal modes leave behind
+        fs::write(&path, vec![0u8; JOURNAL_SECTOR_SIZE]).unwrap();
+        assert!(Journal::read_hot(&path).unwrap().is_none());
+        fs::write(&path, []).unwrap();
+        assert!(Journal::read_hot(&path).unwrap().is_none());
+        fs::write(&path, &JOURNAL_MAGIC[..]).unwrap();
+        assert!(Journal::read_hot(&path).unwrap().is_none());
+
+        // part of a multi-database transaction whose super-journal is gone, so it committed
+        journal(5, &[2]).write(&path).unwrap();
+        let mut data = fs::read(&path).unwrap();
+        let name = b"whatever-sqlite-missing-super-journal";
+        data.extend_from_slice(name);
+        data.extend_from_slice(&(name.len() as u32).to_be_bytes());
+        let sum = name.iter().fold(0u32, |s, &b| s.wrapping_add(b as u32));
+        data.extend_from_slice(&sum.to_be_bytes());
+        data.extend_from_slice(&JOURNAL_MAGIC);
+        fs::write(&path, &data).unwrap();
+        assert!(Journal::read_hot(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_header_is_corrupt() {
+        let temp = TempDb::create("journal-header", PAGE_SIZE, &[]);
+        let path = temp.sibling("-journal");
+        journal(5, &[2]).write(&path).unwrap();
+        let mut data = fs::read(&path).unwrap();
+        write_u32(&mut data, 24, 1000);
+        fs::write(&path, &data).unwrap();
+        assert!(matches!(Journal::read_hot(&path), Err(Error::Corrupt { .. })));
+    }
+
+    #[test]
+    fn open_rolls_back_a_hot_journal() {
+        let temp = TempDb::create("journal-rollback", PAGE_SIZE, &[("t", "CREATE TABLE t(a TEXT)")]);
+        let row = |v: i64| Record {
+            header_size: 0,
+            body: vec![Column::Text(Text::new(&"x".repeat(v as usize % 300)))],
+        };
+        let mut db = temp.open();
+        for v in 1..=50 {
+            db.insert("t", v, &row(v)).unwrap();
+        }
+        let before = fs::read(&temp.path).unwrap();
+        let page_count = db.pager().page_count().unwrap();
+        let original = Journal {
+            initial_size: page_count,
+            page_size: PAGE_SIZE,
+            pages: before.chunks(PAGE_SIZE).enumerate().map(|(i, p)| (i as u32 + 1, p.to_vec())).collect(),
+        };
+        // the transaction reached the database file but crashed before deleting its journal
+        for v in 51..=200 {
+            db.insert("t", v, &row(v)).unwrap();
+        }
+        drop(db);
+        let after = fs::read(&temp.path).unwrap();
+        assert!(after.len() > before.len());
+        original.write(temp.sibling("-journal")).unwrap();
+
+        // read-only connections see the old content without touching the files
+        let mut db = Database::open_with_options(&temp.path, &DatabaseOptions::default()).unwrap();
+        assert_eq!(db.read_table("t").unwrap().len(), 50);
+        assert_eq!(db.pager().page_count().unwrap(), page_count);
+        drop(db);
+        assert_eq!(fs::read(&temp.path).unwrap(), after);
+        assert!(temp.sibling("-journal").exists());
+
+        let mut db = Database::open_with_options(&temp.path, &writable()).unwrap();
+        assert_eq!(db.read_table("t").unwrap().len(), 50);
+        assert!(!temp.sibling("-journal").exists());
+        assert_eq!(fs::read(&temp.path).unwrap(), before);
+        // and the database takes writes again
+        db.insert("t", 51, &row(51)).unwrap();
+        assert_eq!(db.read_table("t").unwrap().len(), 51);
+    }
+}
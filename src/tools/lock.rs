@@ -0,0 +1,147 @@
+use crate::tools::error::{Error, Result};
+use std::fs::File;
+
+/// sqlite locks the bytes from here on, the page holding them is never used for data
+pub(crate) const PENDING_BYTE: u64 = 0x4000_0000;
+const RESERVED_BYTE: u64 = PENDING_BYTE + 1;
+const SHARED_FIRST: u64 = PENDING_BYTE + 2;
+const SHARED_SIZE: u64 = 510;
+
+//######################################################
+// file locks
+//######################################################
+
+/// sqlite's locks on the database file, taken as POSIX advisory locks on the same
+/// bytes so that sqlite connections in other processes honor them. Dropping the
+/// value releases every lock it holds.
+#[cfg_attr(not(all(target_os = "linux", target_pointer_width = "64")), allow(dead_code))]
+pub struct FileLock {
+    fd: i32,
+}
+
+impl FileLock {
+    /// takes the SHARED lock, which readers hold together and which keeps a writer
+    /// from changing the database file. Busy while a writer holds PENDING or EXCLUSIVE.
+    #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+    pub fn shared(file: &File) -> Result<FileLock> {
+        use std::os::unix::io::AsRawFd;
+        let lock = FileLock { fd: file.as_raw_fd() };
+        // new readers are held off by the PENDING byte while a writer waits for them to leave
+        lock.set(sys::F_RDLCK, PENDING_BYTE, 1)?;
+        let shared = lock.set(sys::F_RDLCK, SHARED_FIRST, SHARED_SIZE);
+        lock.set(sys::F_UNLCK, PENDING_BYTE, 1)?;
+        shared?;
+        Ok(lock)
+    }
+
+    #[cfg(not(all(target_os = "linux", target_pointer_width = "64")))]
+    pub fn shared(_file: &File) -> Result<FileLock> {
+        Err(Error::UnsupportedFeature("sqlite file locks on this platform".into()))
+    }
+
+    /// true when another process holds RESERVED, that is it is inside a write transaction
+    pub fn reserved_elsewhere(&self) -> Result<bool> {
+        self.is_locked(RESERVED_BYTE, 1)
+    }
+
+    /// moves from SHARED to RESERVED, which announces a write transaction. Readers keep
+    /// coming and going, but only one connection holds RESERVED. Busy while another does.
+    pub fn reserved(&mut self) -> Result<()> {
+        self.set(sys::F_WRLCK, RESERVED_BYTE, 1)
+    }
+
+    /// moves from SHARED or RESERVED to EXCLUSIVE, needs a file opened for writing.
+    /// Busy while other readers still hold SHARED.
+    pub fn exclusive(&mut self) -> Result<()> {
+        self.set(sys::F_WRLCK, PENDING_BYTE, 1)?;
+        self.set(sys::F_WRLCK, SHARED_FIRST, SHARED_SIZE)
+    }
+
+    #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+    fn set(&self, kind: i16, start: u64, len: u64) -> Result<()> {
+        let mut lock = sys::Flock::new(kind, start, len);
+        // SAFETY: F_SETLK reads the flock struct passed by pointer, it outlives the call
+        if unsafe { sys::fcntl(self.fd, sys::F_SETLK, &mut lock) } == 0 {
+            return Ok(());
+        }
+        let e = std::io::Error::last_os_error();
+        match e.kind() {
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::PermissionDenied => {
+                Err(Error::Busy("another connection holds a conflicting lock".into()))
+            }
+            _ => Err(e.into()),
+        }
+    }
+
+    #[cfg(not(all(target_os = "linux", target_pointer_width = "64")))]
+    fn set(&self, _kind: i16, _start: u64, _len: u64) -> Result<()> {
+        Err(Error::UnsupportedFeature("sqlite file locks on this platform".into()))
+    }
+
+    /// asks whether another process holds a lock that would conflict with a write lock
+    #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+    fn is_locked(&self, start: u64, len: u64) -> Result<bool> {
+        let mut lock = sys::Flock::new(sys::F_WRLCK, start, len);
+        // SAFETY: F_GETLK fills in the flock struct passed by pointer, it outlives the call
+        if unsafe { sys::fcntl(self.fd, sys::F_GETLK, &mut lock) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(lock.l_type != sys::F_UNLCK)
+    }
+
+    #[cfg(not(all(target_os = "linux", target_pointer_width = "64")))]
+    fn is_locked(&self, _start: u64, _len: u64) -> Result<bool> {
+        Err(Error::UnsupportedFeature("sqlite file locks on this platform".into()))
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // nothing to do when unlocking fails, the locks go away with the file anyway
+        #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+        let _ = self.set(sys::F_UNLCK, PENDING_BYTE, SHARED_FIRST + SHARED_SIZE - PENDING_BYTE);
+    }
+}
+
+#[cfg(not(all(target_os = "linux", target_pointer_width = "64")))]
+mod sys {
+    pub const F_WRLCK: i16 = 1;
+}
+
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+mod sys {
+    use std::os::raw::c_int;
+
+    // the values and the struct layout differ between platforms, these are linux ones
+    pub const F_GETLK: c_int = 5;
+    pub const F_SETLK: c_int = 6;
+    pub const F_RDLCK: i16 = 0;
+    pub const F_WRLCK: i16 = 1;
+    pub const F_UNLCK: i16 = 2;
+    const SEEK_SET: i16 = 0;
+
+    #[repr(C)]
+    pub struct Flock {
+        pub l_type: i16,
+        l_whence: i16,
+        l_start: i64,
+        l_len: i64,
+        l_pid: i32,
+    }
+
+    impl Flock {
+        pub fn new(l_type: i16, start: u64, len: u64) -> Flock {
+            Flock {
+                l_type,
+                l_whence: SEEK_SET,
+                l_start: start as i64,
+                l_len: len as i64,
+                l_pid: 0,
+            }
+        }
+    }
+
+    extern "C" {
+        pub fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+    }
+}
@@ -0,0 +1,457 @@
+use crate::tools::{
+    cache::{CacheStats, PageCache},
+    defs::{SqliteHeader, HEADER_SIZE},
+    error::{Error, Result},
+    journal::Journal,
+    lock::FileLock,
+    mmap::Mmap,
+    wal::Wal,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{Seek, Write},
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+#[derive(Debug, Clone)]
+pub struct DatabaseOptions {
+    /// never write to the database, a hot journal is then applied in memory only
+    pub read_only: bool,
+    /// byte budget of the page cache, 0 turns it off
+    pub cache_bytes: usize,
+    /// parse pages straight out of a memory mapping of the file, the cache is
+    /// skipped for them. Falls back to reads when the file can not be mapped.
+    /// Pages are only handed out while the file still holds them, but a truncation
+    /// by another process while one is being parsed still ends this one with SIGBUS.
+    pub mmap: bool,
+}
+
+impl Default for DatabaseOptions {
+    fn default() -> DatabaseOptions {
+        DatabaseOptions {
+            read_only: true,
+            // same budget as sqlite's default cache_size of -2000
+            cache_bytes: 2000 * 1024,
+            mmap: false,
+        }
+    }
+}
+
+//######################################################
+// pager
+//######################################################
+
+/// owns the database file and the parsed header, every page read goes through here
+pub struct Pager {
+    path: PathBuf,
+    file: File,
+    header: SqliteHeader,
+    wal: Option<Wal>,
+    /// length of the -wal file when it was parsed
+    wal_len: u64,
+    /// original pages of an interrupted transaction that could not be rolled back on disk
+    journal: Option<Journal>,
+    cache: PageCache,
+    map: Option<Mmap>,
+    read_only: bool,
+}
+
+impl Pager {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Pager> {
+        Pager::open_with_options(path, &DatabaseOptions::default())
+    }
+
+    pub fn open_with_options<P: AsRef<Path>>(path: P, options: &DatabaseOptions) -> Result<Pager> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new().read(true).write(!options.read_only).open(path)?;
+        let journal_path = sibling_path(path, "-journal");
+        let mut journal = Journal::read_hot(&journal_path)?;
+        if journal.is_some() {
+            // like sqlite, decide under a SHARED lock: while another connection holds
+            // RESERVED the journal belongs to its open transaction and is not hot
+            match FileLock::shared(&file) {
+                Ok(mut lock) => {
+                    if lock.reserved_elsewhere()? {
+                        journal = None;
+                    } else if let (Some(j), false) = (&journal, options.read_only) {
+                        lock.exclusive()?;
+                        roll_back(&mut file, j)?;
+                        fs::remove_file(&journal_path)?;
+                        journal = None;
+                    }
+                }
+                // without locks a journal that looks hot is taken as hot, reading applies
+                // it in memory but writing it back could undo another writer's transaction
+                Err(Error::UnsupportedFeature(_)) if options.read_only => {}
+                Err(Error::UnsupportedFeature(_)) => {
+                    return Err(Error::UnsupportedFeature(
+                        "rolling back a hot journal without sqlite file locks, open read-only instead".into(),
+                    ))
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let header = match journal.as_ref().and_then(|j| j.pages.get(&1)) {
+            Some(page) => header_from_page(page)?,
+            None => read_header(&mut file)?,
+        };
+        let mut pager = Pager {
+            path: path.to_path_buf(),
+            file,
+            header,
+            wal: None,
+            wal_len: 0,
+            journal,
+            cache: PageCache::new(options.cache_bytes),
+            map: None,
+            read_only: options.read_only,
+        };
+        if options.mmap {
+            pager.map = Mmap::map(&pager.file).ok();
+        }
+        pager.load_wal()?;
+        Ok(pager)
+    }
+
+    /// read version 2 marks a database in WAL mode
+    fn load_wal(&mut self) -> Result<()> {
+        self.wal = None;
+        self.wal_len = 0;
+        if self.header.file_format_read_version != 2 || self.journal.is_some() {
+            return Ok(());
+        }
+        let wal_path = sibling_path(&self.path, "-wal");
+        self.wal_len = fs::metadata(&wal_path).map(|m| m.len()).unwrap_or(0);
+        self.wal = Wal::open(wal_path, self.header.page_size())?;
+        // the log may hold a newer page 1 and with it a newer header
+        if let Some(page) = self.wal.as_mut().map(|w| w.read_page(1)).transpose()?.flatten() {
+            self.header = header_from_page(&page)?;
+        }
+        Ok(())
+    }
+
+    /// drops cached pages when another connection changed the database since the
+    /// last call, detected by the file change counter or, in WAL mode, by new frames
+    /// or a restarted log. Returns true in that case.
+    pub fn check_for_changes(&mut self) -> Result<bool> {
+        // a hot journal applied in memory is a fixed snapshot
+        if self.journal.is_some() {
+            return Ok(false);
+        }
+        let mut buf = [0u8; HEADER_SIZE];
+        self.file.seek(std::io::SeekFrom::Start(0))?;
+        read_exact_or_corrupt(&mut self.file, &mut buf)?;
+        // read version at offset 19, 2 in WAL mode
+        let wal_changed = if buf[19] == 2 {
+            let wal_path = sibling_path(&self.path, "-wal");
+            match &self.wal {
+                Some(wal) => wal.changed(&wal_path)?,
+                // without a commit in the log when it was read only its length is compared
+                None => fs::metadata(&wal_path).map(|m| m.len()).unwrap_or(0) != self.wal_len,
+            }
+        } else {
+            self.wal_len != 0
+        };
+        let counter = u32::from_be_bytes([buf[24], buf[25], buf[26], buf[27]]);
+        if counter == self.header.file_change_counter && !wal_changed {
+            return Ok(false);
+        }
+        self.cache.clear();
+        if self.map.is_some() {
+            self.map = Mmap::map(&self.file).ok();
+        }
+        self.header = SqliteHeader::new(&buf)?;
+        self.load_wal()?;
+        Ok(true)
+    }
+
+    /// errors unless `commit` can write to this database
+    pub fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::UnsupportedFeature("writing to a database opened read-only".into()));
+        }
+        if self.header.file_format_write_version == 2 || self.wal.is_some() {
+            return Err(Error::UnsupportedFeature("writing to a database in WAL mode".into()));
+        }
+        Ok(())
+    }
+
+    /// writes the pages atomically: their original content goes to a rollback journal
+    /// first, which is deleted once the new content is on disk. A new header must be
+    /// part of page 1. Takes sqlite's locks on the way, RESERVED before the journal is
+    /// written and EXCLUSIVE before the database file is, and releases them at the end.
+    /// Busy when another connection holds one of them in the way, or committed since the
+    /// pages were read.
+    pub fn commit(&mut self, pages: &BTreeMap<u32, Vec<u8>>) -> Result<()> {
+        self.check_writable()?;
+        let page_size = self.header.page_size();
+        // nothing may reach the disk when any of the pages is bad
+        if let Some((page, data)) = pages.iter().find(|(&page, data)| data.len() != page_size || page == 0) {
+            return Err(Error::corrupt(format!("write of {} bytes to page {}", data.len(), page)));
+        }
+        let mut lock = FileLock::shared(&self.file)?;
+        if read_header(&mut self.file)?.file_change_counter != self.header.file_change_counter {
+            return Err(Error::Busy("the database changed since the pages were read".into()));
+        }
+        lock.reserved()?;
+        let initial_size = self.page_count()?;
+        let mut journal = Journal {
+            initial_size,
+            page_size,
+            pages: HashMap::new(),
+        };
+        // pages past the old end of the file are cut off again on rollback
+        for &page in pages.keys().filter(|p| **p <= initial_size) {
+            journal.pages.insert(page, self.read_raw_page(page as usize)?.to_vec());
+        }
+        let journal_path = sibling_path(&self.path, "-journal");
+        journal.write(&journal_path)?;
+        // readers that are still around keep the file from changing under them
+        if let Err(e) = lock.exclusive() {
+            fs::remove_file(&journal_path)?;
+            return Err(e);
+        }
+
+        for (&page, data) in pages {
+            self.file.seek(std::io::SeekFrom::Start(page_size as u64 * (page as u64 - 1)))?;
+            self.file.write_all(data)?;
+        }
+        self.file.sync_all()?;
+        fs::remove_file(&journal_path)?;
+        drop(lock);
+
+        for (&page, data) in pages {
+            self.cache.insert(page, Arc::from(data.as_slice()));
+        }
+        if let Some(page) = pages.get(&1) {
+            self.header = header_from_page(page)?;
+        }
+        // the mapping does not grow with the file
+        if self.map.is_some() {
+            self.map = Mmap::map(&self.file).ok();
+        }
+        Ok(())
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    pub fn header(&self) -> &SqliteHeader {
+        &self.header
+    }
+
+    /// size of the database in pages as readers see it
+    pub fn page_count(&self) -> Result<u32> {
+        if let Some(journal) = &self.journal {
+            return Ok(journal.initial_size);
+        }
+        if let Some(wal) = &self.wal {
+            return Ok(wal.database_size());
+        }
+        if self.header.database_size_valid() {
+            return Ok(self.header.database_size);
+        }
+        // files written by old versions only have the real size
+        Ok((self.file.metadata()?.len() / self.header.page_size() as u64) as u32)
+    }
+
+    /// borrows the page from the memory mapping when it is current there, reads it otherwise
+    pub fn page_bytes(&mut self, page: usize) -> Result<PageBytes<'_>> {
+        let page_size = self.header.page_size();
+        // pages of a journal overlay or the log take precedence over the main file
+        let in_main_file = page != 0
+            && self.journal.is_none()
+            && !self.wal.as_ref().is_some_and(|w| w.contains(page as u32));
+        let range = (page_size * page.saturating_sub(1))..(page_size * page);
+        if in_main_file && self.map.is_some() {
+            // touching a mapped page past the end of a file another connection truncated
+            // raises SIGBUS, a shrunken file gets a new mapping of what is left
+            let file_len = self.file.metadata()?.len();
+            if self.map.as_ref().is_some_and(|m| m.len() as u64 > file_len) {
+                self.map = Mmap::map(&self.file).ok();
+            }
+        }
+        let mapped = in_main_file && self.map.as_ref().is_some_and(|m| range.end <= m.len());
+        if !mapped {
+            return self.read_raw_page(page).map(PageBytes::Shared);
+        }
+        let map = self.map.as_ref().map_or(&[][..], |m| m.as_slice());
+        Ok(PageBytes::Mapped(&map[range]))
+    }
+
+    /// the page as cached, shared with the cache rather than copied
+    pub fn read_raw_page(&mut self, page: usize) -> Result<Arc<[u8]>> {
+        if page == 0 {
+            return Err(Error::corrupt("reference to page 0"));
+        }
+        if let Some(buf) = self.cache.get(page as u32) {
+            return Ok(buf);
+        }
+        let buf: Arc<[u8]> = self.load_page(page)?.into();
+        self.cache.insert(page as u32, Arc::clone(&buf));
+        Ok(buf)
+    }
+
+    /// reads a page from the journal overlay, the log or the main file, in that order
+    fn load_page(&mut self, page: usize) -> Result<Vec<u8>> {
+        if let Some(journal) = &self.journal {
+            if page as u32 > journal.initial_size {
+                return Err(Error::corrupt("page past the end of the rolled back database").on_page(page as u32));
+            }
+            if let Some(buf) = journal.pages.get(&(page as u32)) {
+                return Ok(buf.clone());
+            }
+        }
+        if let Some(wal) = &mut self.wal {
+            if let Some(buf) = wal.read_page(page as u32)? {
+                return Ok(buf);
+            }
+        }
+        let page_size = self.header.page_size();
+        let mut buf = vec![0u8; page_size];
+        self.file
+            .seek(std::io::SeekFrom::Start((page_size * (page - 1)) as u64))?;
+        read_exact_or_corrupt(&mut self.file, &mut buf).map_err(|e| e.on_page(page as u32))?;
+        Ok(buf)
+    }
+}
+
+/// a page handed out by the pager, borrowed from the memory mapping or shared with the cache
+pub enum PageBytes<'a> {
+    Mapped(&'a [u8]),
+    Shared(Arc<[u8]>),
+}
+
+impl PageBytes<'_> {
+    /// detaches the page from the pager, only a mapped page has to be copied
+    pub fn into_owned(self) -> PageBytes<'static> {
+        match self {
+            PageBytes::Mapped(data) => PageBytes::Shared(data.into()),
+            PageBytes::Shared(data) => PageBytes::Shared(data),
+        }
+    }
+}
+
+impl Deref for PageBytes<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            PageBytes::Mapped(data) => data,
+            PageBytes::Shared(data) => data,
+        }
+    }
+}
+
+/// `suffix` appended to the database file name, like `-wal` or `-journal`
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// writes the original pages back and cuts the file to its size before the transaction
+fn roll_back(file: &mut File, journal: &Journal) -> Result<()> {
+    for (page, data) in &journal.pages {
+        file.seek(std::io::SeekFrom::Start(journal.page_size as u64 * (*page as u64 - 1)))?;
+        file.write_all(data)?;
+    }
+    file.set_len(journal.page_size as u64 * journal.initial_size as u64)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+fn header_from_page(page: &[u8]) -> Result<SqliteHeader> {
+    let mut buf = [0u8; HEADER_SIZE];
+    buf.copy_from_slice(&page[..HEADER_SIZE]);
+    SqliteHeader::new(&buf)
+}
+
+fn read_header(f: &mut File) -> Result<SqliteHeader> {
+    let mut buf = [0u8; HEADER_SIZE];
+    f.seek(std::io::SeekFrom::Start(0))?;
+    read_exact_or_corrupt(f, &mut buf)?;
+    SqliteHeader::new(&buf)
+}
+
+/// a short read means the file is truncated, which is reported as corruption
+fn read_exact_or_corrupt(f: &mut File, buf: &mut [u8]) -> Result<()> {
+    match std::io::Read::read_exact(f, buf) {
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            Err(Error::corrupt("read past end of file"))
+        }
+        r => Ok(r?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::{
+        btree,
+        defs::{Column, Record, TextEncoding},
+        testing::{writable, TempDb},
+    };
+
+    fn payload(v: i64) -> Vec<u8> {
+        let record = Record {
+            header_size: 0,
+            body: vec![Column::I64(v)],
+        };
+        record.to_bytes(TextEncoding::Utf8, 4)
+    }
+
+    #[test]
+    fn mapped_pages_stay_inside_a_truncated_file() {
+        let temp = TempDb::create("pager-mmap", 512, &[("t", "CREATE TABLE t(a)"), ("u", "CREATE TABLE u(a)")]);
+        let options = DatabaseOptions {
+            mmap: true,
+            ..Default::default()
+        };
+        let mut pager = Pager::open_with_options(&temp.path, &options).unwrap();
+        assert!(matches!(pager.page_bytes(3).unwrap(), PageBytes::Mapped(_)));
+        fs::OpenOptions::new().write(true).open(&temp.path).unwrap().set_len(1024).unwrap();
+        // page 3 is no longer in the file and must not be touched through the old mapping
+        assert!(pager.page_bytes(3).is_err());
+        assert!(matches!(pager.page_bytes(2).unwrap(), PageBytes::Mapped(_)));
+    }
+
+    #[test]
+    fn commit_is_busy_after_another_connection_committed() {
+        let temp = TempDb::create("pager-stale", 512, &[("t", "CREATE TABLE t(a)")]);
+        let mut first = Pager::open_with_options(&temp.path, &writable()).unwrap();
+        let mut second = Pager::open_with_options(&temp.path, &writable()).unwrap();
+        btree::insert(&mut first, 2, 1, &payload(1)).unwrap();
+        let before = fs::read(&temp.path).unwrap();
+        // the second connection still works from the pages it read before
+        assert!(matches!(btree::insert(&mut second, 2, 2, &payload(2)), Err(Error::Busy(_))));
+        assert_eq!(fs::read(&temp.path).unwrap(), before);
+        assert!(second.check_for_changes().unwrap());
+        btree::insert(&mut second, 2, 2, &payload(2)).unwrap();
+    }
+
+    #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+    #[test]
+    fn commit_is_busy_while_another_process_holds_a_lock() {
+        use crate::tools::testing::LockHolder;
+
+        let temp = TempDb::create("pager-locked", 512, &[("t", "CREATE TABLE t(a)")]);
+        let mut pager = Pager::open_with_options(&temp.path, &writable()).unwrap();
+        let before = fs::read(&temp.path).unwrap();
+        // a writer in its transaction, then a reader the commit has to wait for
+        for reserved in [true, false] {
+            let holder = LockHolder::start(&temp.path, reserved);
+            assert!(matches!(btree::insert(&mut pager, 2, 1, &payload(1)), Err(Error::Busy(_))));
+            assert_eq!(fs::read(&temp.path).unwrap(), before);
+            assert!(!temp.sibling("-journal").exists());
+            drop(holder);
+        }
+        btree::insert(&mut pager, 2, 1, &payload(1)).unwrap();
+        assert!(!temp.sibling("-journal").exists());
+    }
+}
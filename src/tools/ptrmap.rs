@@ -0,0 +1,218 @@
+use crate::tools::{
+    defs::{PointerMapEntry, PointerMapPage, SqliteHeader},
+    error::{Error, Result},
+    lock::PENDING_BYTE,
+    pager::Pager,
+};
+
+//######################################################
+// pointer map
+//######################################################
+
+/// pointer map pages only exist in auto-vacuum and incremental-vacuum databases
+pub fn has_pointer_map(header: &SqliteHeader) -> bool {
+    header.largest_root_page != 0
+}
+
+pub fn lock_byte_page(header: &SqliteHeader) -> u32 {
+    (PENDING_BYTE / header.page_size() as u64) as u32 + 1
+}
+
+/// number of pages described by one pointer map page
+fn entries_per_page(header: &SqliteHeader) -> u32 {
+    header.usable_size() as u32 / 5
+}
+
+/// the pointer map page that holds the entry of `page`, 0 for page 1
+pub fn pointer_map_page_for(header: &SqliteHeader, page: u32) -> u32 {
+    if page < 2 {
+        return 0;
+    }
+    // a pointer map page followed by the pages it describes
+    let group = entries_per_page(header) + 1;
+    let map_page = (page - 2) / group * group + 2;
+    // the lock byte page can not hold anything, its map page moves one down
+    if map_page == lock_byte_page(header) {
+        map_page + 1
+    } else {
+        map_page
+    }
+}
+
+pub fn is_pointer_map_page(header: &SqliteHeader, page: u32) -> bool {
+    has_pointer_map(header) && pointer_map_page_for(header, page) == page
+}
+
+pub fn read_pointer_map_page(pager: &mut Pager, page: u32) -> Result<PointerMapPage> {
+    if !is_pointer_map_page(pager.header(), page) {
+        return Err(Error::NotFound(format!("page {} is not a pointer map page", page)));
+    }
+    let pages = described_pages(pager.header(), page, pager.page_count()?);
+    let raw = pager.read_raw_page(page as usize)?;
+    PointerMapPage::new(&raw, page, &pages).map_err(|e| e.on_page(page))
+}
+
+/// pages up to `page_count` with an entry on the pointer map page `map_page`. The
+/// lock-byte page has none, and a map page moved past it leaves its last slot unused.
+fn described_pages(header: &SqliteHeader, map_page: u32, page_count: u32) -> Vec<u32> {
+    let last = map_page.saturating_add(entries_per_page(header)).min(page_count);
+    (map_page + 1..=last)
+        .filter(|&p| p != lock_byte_page(header) && pointer_map_page_for(header, p) == map_page)
+        .collect()
+}
+
+/// type and parent of `page` as recorded in the pointer map
+pub fn pointer_map_entry(pager: &mut Pager, page: u32) -> Result<PointerMapEntry> {
+    if !has_pointer_map(pager.header()) {
+        return Err(Error::NotFound("database has no pointer map".into()));
+    }
+    let map_page = pointer_map_page_for(pager.header(), page);
+    if map_page == 0 || map_page == page || page == lock_byte_page(pager.header()) {
+        return Err(Error::NotFound(format!("page {} has no pointer map entry", page)));
+    }
+    read_pointer_map_page(pager, map_page)?
+        .entries
+        .into_iter()
+        .find(|e| e.page == page)
+        .ok_or_else(|| Error::NotFound(format!("page {} is past the end of the database", page)))
+}
+
+/// entries of all pointer map pages in page order, empty without auto-vacuum
+pub fn read_pointer_map(pager: &mut Pager) -> Result<Vec<PointerMapEntry>> {
+    let mut entries = vec![];
+    if !has_pointer_map(pager.header()) {
+        return Ok(entries);
+    }
+    let page_count = pager.page_count()?;
+    let mut page = 2;
+    while page <= page_count {
+        let map_page = pointer_map_page_for(pager.header(), page);
+        entries.extend(read_pointer_map_page(pager, map_page)?.entries);
+        page = map_page + entries_per_page(pager.header()) + 1;
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::{
+        defs::PointerMapType,
+        helper::write_u32,
+        testing::{header_bytes, TempDb},
+    };
+    use std::{
+        collections::BTreeMap,
+        fs::OpenOptions,
+        io::{Seek, SeekFrom, Write},
+    };
+
+    fn auto_vacuum_header(page_size: usize, reserved: u8, page_count: u32) -> [u8; 100] {
+        let mut buf = header_bytes(page_size, page_count);
+        buf[20] = reserved;
+        write_u32(&mut buf, 52, 1);
+        buf
+    }
+
+    fn header_of(page_size: usize, reserved: u8) -> SqliteHeader {
+        SqliteHeader::new(&auto_vacuum_header(page_size, reserved, 1)).unwrap()
+    }
+
+    #[test]
+    fn map_page_and_slot_math() {
+        // (page size, reserved, page, its map page)
+        let cases = [
+            (512, 0, 1, 0),
+            (512, 0, 2, 2),
+            (512, 0, 3, 2),
+            (512, 0, 104, 2),
+            (512, 0, 105, 105),
+            (512, 0, 106, 105),
+            (512, 32, 98, 2),
+            (512, 32, 99, 99),
+            (4096, 0, 821, 2),
+            (4096, 0, 822, 822),
+            // the lock-byte page 262145 sits inside the group of 261622
+            (4096, 0, 262_145, 261_582),
+            // 1048577 is the lock-byte page and would be a map page, the next page is instead
+            (1024, 0, 1_048_576, 1_048_372),
+            (1024, 0, 1_048_577, 1_048_578),
+            (1024, 0, 1_048_578, 1_048_578),
+            (1024, 0, 1_048_781, 1_048_578),
+            (1024, 0, 1_048_782, 1_048_782),
+        ];
+        for (page_size, reserved, page, map_page) in cases {
+            let header = header_of(page_size, reserved);
+            assert_eq!(pointer_map_page_for(&header, page), map_page, "page {} at {}/{}", page, page_size, reserved);
+        }
+
+        let header = header_of(4096, 0);
+        assert_eq!(lock_byte_page(&header), 262_145);
+        let pages = described_pages(&header, 261_582, u32::MAX);
+        assert_eq!(pages.len(), 818);
+        assert!(!pages.contains(&262_145));
+        assert_eq!(described_pages(&header, 2, 10), (3..=10).collect::<Vec<_>>());
+
+        // the moved map page describes one page less, its last slot stays unused
+        let header = header_of(1024, 0);
+        assert_eq!(lock_byte_page(&header), 1_048_577);
+        assert_eq!(described_pages(&header, 1_048_578, u32::MAX), (1_048_579..=1_048_781).collect::<Vec<_>>());
+        assert_eq!(described_pages(&header, 1_048_372, u32::MAX), (1_048_373..=1_048_576).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn entries_skip_the_slot_of_the_lock_byte_page() {
+        let mut raw = vec![0u8; 512];
+        for (slot, page_type) in [(0, 1), (2, 5)] {
+            raw[slot * 5] = page_type;
+            write_u32(&mut raw, slot * 5 + 1, slot as u32);
+        }
+        let map = PointerMapPage::new(&raw, 10, &[11, 13]).unwrap();
+        let entries: Vec<_> = map.entries.iter().map(|e| (e.page, e.page_type, e.parent)).collect();
+        assert_eq!(entries, [(11, PointerMapType::RootPage, 0), (13, PointerMapType::BTree, 2)]);
+        assert!(PointerMapPage::new(&raw, 10, &[11, 12]).is_err());
+    }
+
+    /// sparse files past the lock-byte page, every page but the lock-byte page has an entry
+    /// with itself as parent
+    #[test]
+    fn read_pointer_map_past_the_lock_byte_page() {
+        for page_size in [65536, 1024] {
+            let temp = TempDb::create(&format!("ptrmap-{}", page_size), page_size, &[]);
+            let header = header_of(page_size, 0);
+            let lock_page = lock_byte_page(&header);
+            let page_count = lock_page + 300;
+
+            let mut maps = BTreeMap::new();
+            for page in 2..=page_count {
+                let map_page = pointer_map_page_for(&header, page);
+                if map_page == page || page == lock_page {
+                    continue;
+                }
+                let map = maps.entry(map_page).or_insert_with(|| vec![0u8; page_size]);
+                let offset = (page - map_page - 1) as usize * 5;
+                map[offset] = 5;
+                write_u32(map, offset + 1, page);
+            }
+            let mut file = OpenOptions::new().write(true).open(&temp.path).unwrap();
+            file.write_all(&auto_vacuum_header(page_size, 0, page_count)).unwrap();
+            file.set_len(page_count as u64 * page_size as u64).unwrap();
+            for (map_page, data) in &maps {
+                file.seek(SeekFrom::Start((*map_page as u64 - 1) * page_size as u64)).unwrap();
+                file.write_all(data).unwrap();
+            }
+            drop(file);
+
+            let mut pager = Pager::open(&temp.path).unwrap();
+            let entries = read_pointer_map(&mut pager).unwrap();
+            let expected: Vec<u32> = (2..=page_count)
+                .filter(|&p| p != lock_page && !maps.contains_key(&p))
+                .collect();
+            assert_eq!(entries.iter().map(|e| e.page).collect::<Vec<_>>(), expected);
+            assert!(entries.iter().all(|e| e.parent == e.page && e.page_type == PointerMapType::BTree));
+
+            assert!(matches!(pointer_map_entry(&mut pager, lock_page), Err(Error::NotFound(_))));
+            assert_eq!(pointer_map_entry(&mut pager, lock_page + 2).unwrap().parent, lock_page + 2);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use crate::tools::{
+    defs::*,
+    error::{Error, Result},
+};
+
+//######################################################
+// schema
+//######################################################
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectType {
+    Table,
+    Index,
+    View,
+    Trigger,
+}
+
+impl ObjectType {
+    pub fn parse(s: &str) -> Option<ObjectType> {
+        match s {
+            "table" => Some(ObjectType::Table),
+            "index" => Some(ObjectType::Index),
+            "view" => Some(ObjectType::View),
+            "trigger" => Some(ObjectType::Trigger),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectType::Table => "table",
+            ObjectType::Index => "index",
+            ObjectType::View => "view",
+            ObjectType::Trigger => "trigger",
+        }
+    }
+}
+
+/// row of the sqlite_schema table, views and triggers have root page 0
+#[derive(Debug)]
+pub struct SchemaEntry {
+    pub object_type: ObjectType,
+    pub name: String,
+    pub tbl_name: String,
+    pub root_page: u32,
+    pub sql: Option<String>,
+}
+
+impl SchemaEntry {
+    pub fn new(record: &Record) -> Result<SchemaEntry> {
+        if record.body.len() < 5 {
+            return Err(Error::corrupt(format!("sqlite_schema row has {} columns", record.body.len())));
+        }
+        let object_type = column_to_string(&record.body[0])?;
+        let object_type = ObjectType::parse(&object_type)
+            .ok_or_else(|| Error::corrupt(format!("unknown schema object type {}", object_type)))?;
+        Ok(SchemaEntry {
+            object_type,
+            name: column_to_string(&record.body[1])?,
+            tbl_name: column_to_string(&record.body[2])?,
+            root_page: match record.body[3] {
+                Column::I64(v) => v as u32,
+                _ => 0,
+            },
+            sql: match &record.body[4] {
+                Column::NULL => None,
+                c => Some(column_to_string(c)?),
+            },
+        })
+    }
+}
+
+//######################################################
+
+#[derive(Debug, Default)]
+pub struct Schema {
+    entries: Vec<SchemaEntry>,
+}
+
+impl Schema {
+    pub fn new(entries: Vec<SchemaEntry>) -> Schema {
+        Schema { entries }
+    }
+
+    pub fn entries(&self) -> &[SchemaEntry] {
+        &self.entries
+    }
+
+    /// object names are case insensitive in sqlite
+    pub fn get(&self, name: &str) -> Option<&SchemaEntry> {
+        self.entries.iter().find(|e| e.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn table(&self, name: &str) -> Option<&SchemaEntry> {
+        self.get_typed(ObjectType::Table, name)
+    }
+
+    pub fn index(&self, name: &str) -> Option<&SchemaEntry> {
+        self.get_typed(ObjectType::Index, name)
+    }
+
+    pub fn view(&self, name: &str) -> Option<&SchemaEntry> {
+        self.get_typed(ObjectType::View, name)
+    }
+
+    pub fn trigger(&self, name: &str) -> Option<&SchemaEntry> {
+        self.get_typed(ObjectType::Trigger, name)
+    }
+
+    fn get_typed(&self, object_type: ObjectType, name: &str) -> Option<&SchemaEntry> {
+        self.entries
+            .iter()
+            .find(|e| e.object_type == object_type && e.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn tables(&self) -> impl Iterator<Item = &SchemaEntry> {
+        self.of_type(ObjectType::Table)
+    }
+
+    pub fn indexes(&self) -> impl Iterator<Item = &SchemaEntry> {
+        self.of_type(ObjectType::Index)
+    }
+
+    pub fn of_type(&self, object_type: ObjectType) -> impl Iterator<Item = &SchemaEntry> {
+        self.entries.iter().filter(move |e| e.object_type == object_type)
+    }
+
+    /// indexes whose tbl_name is `table`, including the automatic ones
+    pub fn indexes_for_table(&self, table: &str) -> Vec<&SchemaEntry> {
+        self.indexes()
+            .filter(|e| e.tbl_name.eq_ignore_ascii_case(table))
+            .collect()
+    }
+}
+
+fn column_to_string(c: &Column) -> Result<String> {
+    match c {
+        Column::Text(t) => Ok(t.value.clone()),
+        c => Err(Error::TypeMismatch(format!("expected text in sqlite_schema, found {:?}", c))),
+    }
+}
@@ -0,0 +1,205 @@
+use crate::tools::{
+    btree,
+    database::Database,
+    defs::*,
+    helper::{write_u16, write_u32},
+    pager::{DatabaseOptions, Pager},
+};
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    process::{Child, ChildStdout, Command, Stdio},
+};
+
+//######################################################
+// test databases
+//######################################################
+
+/// a database file in the temp directory, removed together with its journal and log on drop
+pub struct TempDb {
+    pub path: PathBuf,
+}
+
+impl TempDb {
+    /// an empty database with `page_size` byte pages and an empty rowid table for each
+    /// `(name, sql)`, their root pages follow page 1 in the given order
+    pub fn create(name: &str, page_size: usize, tables: &[(&str, &str)]) -> TempDb {
+        let path = std::env::temp_dir().join(format!("whatever-sqlite-{}-{}.db", std::process::id(), name));
+        let db = TempDb { path };
+        db.remove_files();
+
+        let page_count = 1 + tables.len();
+        let mut data = vec![0u8; page_size * page_count];
+        data[..HEADER_SIZE].copy_from_slice(&header_bytes(page_size, page_count as u32));
+        for page in 0..page_count {
+            let offset = page * page_size + if page == 0 { HEADER_SIZE } else { 0 };
+            data[offset] = 0x0d;
+            // cell content starts at the end of the page, 0 standing for 65536
+            write_u16(&mut data, offset + 5, page_size as u16);
+        }
+        fs::write(&db.path, data).unwrap();
+
+        let mut pager = Pager::open_with_options(&db.path, &writable()).unwrap();
+        for (i, (table, sql)) in tables.iter().enumerate() {
+            let record = Record {
+                header_size: 0,
+                body: vec![
+                    Column::Text(Text::new("table")),
+                    Column::Text(Text::new(table)),
+                    Column::Text(Text::new(table)),
+                    Column::I64(i as i64 + 2),
+                    Column::Text(Text::new(sql)),
+                ],
+            };
+            let payload = record.to_bytes(TextEncoding::Utf8, 4);
+            btree::insert(&mut pager, 1, i as i64 + 1, &payload).unwrap();
+        }
+        db
+    }
+
+    /// opens the database for reading and writing
+    pub fn open(&self) -> Database {
+        Database::open_with_options(&self.path, &writable()).unwrap()
+    }
+
+    pub fn sibling(&self, suffix: &str) -> PathBuf {
+        let mut name = OsString::from(self.path.as_os_str());
+        name.push(suffix);
+        PathBuf::from(name)
+    }
+
+    fn remove_files(&self) {
+        for path in [self.path.clone(), self.sibling("-journal"), self.sibling("-wal")] {
+            remove_if_exists(&path);
+        }
+    }
+}
+
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        self.remove_files();
+    }
+}
+
+/// the header of a UTF-8 database with schema format 4 and `page_count` pages
+pub fn header_bytes(page_size: usize, page_count: u32) -> [u8; HEADER_SIZE] {
+    let mut data = [0u8; HEADER_SIZE];
+    data[..16].copy_from_slice(b"SQLite format 3\0");
+    // 65536 is stored as 1
+    write_u16(&mut data, 16, if page_size == 65536 { 1 } else { page_size as u16 });
+    data[18] = 1;
+    data[19] = 1;
+    data[21] = 64;
+    data[22] = 32;
+    data[23] = 32;
+    write_u32(&mut data, 24, 1);
+    write_u32(&mut data, 28, page_count);
+    write_u32(&mut data, 44, 4);
+    write_u32(&mut data, 56, 1);
+    write_u32(&mut data, 92, 1);
+    write_u32(&mut data, 96, 3_045_000);
+    data
+}
+
+pub fn writable() -> DatabaseOptions {
+    DatabaseOptions {
+        read_only: false,
+        ..Default::default()
+    }
+}
+
+fn remove_if_exists(path: &Path) {
+    if let Err(e) = fs::remove_file(path) {
+        assert_eq!(e.kind(), std::io::ErrorKind::NotFound, "removing {}", path.display());
+    }
+}
+
+/// xorshift, enough to shuffle keys and vary sizes the same way on every run
+pub struct Rng(pub u64);
+
+impl Rng {
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// in `0..n`
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+//######################################################
+// locks held by another process
+//######################################################
+
+/// POSIX locks of one process never conflict with each other, so the other connection
+/// is this test binary again, running `lock_holder` until the value is dropped
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+pub struct LockHolder {
+    child: Child,
+    stdout: BufReader<ChildStdout>,
+}
+
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+impl LockHolder {
+    /// returns once the child holds SHARED, and RESERVED too when `reserved` is set
+    pub fn start(path: &Path, reserved: bool) -> LockHolder {
+        let mut child = Command::new(std::env::current_exe().unwrap())
+            .args(["tools::testing::lock_holder", "--exact", "--ignored", "--nocapture", "--test-threads=1"])
+            .env("WHATEVER_SQLITE_LOCK_PATH", path)
+            .env("WHATEVER_SQLITE_LOCK_RESERVED", if reserved { "1" } else { "0" })
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut line = String::new();
+        while stdout.read_line(&mut line).unwrap() > 0 {
+            // libtest may print the test name on the same line
+            if line.trim_end().ends_with("locked") {
+                return LockHolder { child, stdout };
+            }
+            line.clear();
+        }
+        let _ = child.wait();
+        panic!("lock holder exited without taking the lock");
+    }
+}
+
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+impl Drop for LockHolder {
+    fn drop(&mut self) {
+        // closing its input lets the child finish, its last output is read so it can
+        drop(self.child.stdin.take());
+        let _ = self.stdout.read_to_end(&mut vec![]);
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+#[test]
+#[ignore = "run by LockHolder in a child process"]
+fn lock_holder() {
+    let (Ok(path), Ok(reserved)) = (
+        std::env::var("WHATEVER_SQLITE_LOCK_PATH"),
+        std::env::var("WHATEVER_SQLITE_LOCK_RESERVED"),
+    ) else {
+        return;
+    };
+    let file = fs::OpenOptions::new().read(true).write(true).open(path).unwrap();
+    let mut lock = crate::tools::lock::FileLock::shared(&file).unwrap();
+    if reserved == "1" {
+        lock.reserved().unwrap();
+    }
+    println!("locked");
+    std::io::stdout().flush().unwrap();
+    std::io::stdin().read_to_end(&mut vec![]).unwrap();
+}
@@ -0,0 +1,440 @@
+use crate::tools::{
+    defs::*,
+    error::{Error, Result},
+    helper::{read_u16, read_u32, read_var_int},
+};
+use std::borrow::Cow;
+
+//######################################################
+// page view
+//######################################################
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageKind {
+    IndexInterior,
+    TableInterior,
+    IndexLeaf,
+    TableLeaf,
+}
+
+impl PageKind {
+    pub fn from_flag(flag: u8) -> Option<PageKind> {
+        match flag {
+            0x02 => Some(PageKind::IndexInterior),
+            0x05 => Some(PageKind::TableInterior),
+            0x0a => Some(PageKind::IndexLeaf),
+            0x0d => Some(PageKind::TableLeaf),
+            _ => None,
+        }
+    }
+
+    pub fn is_leaf(self) -> bool {
+        matches!(self, PageKind::IndexLeaf | PageKind::TableLeaf)
+    }
+
+    pub fn is_table(self) -> bool {
+        matches!(self, PageKind::TableInterior | PageKind::TableLeaf)
+    }
+
+    /// 12 bytes for interior pages, which end with the right-most pointer
+    pub fn header_size(self) -> usize {
+        if self.is_leaf() {
+            8
+        } else {
+            12
+        }
+    }
+}
+
+/// b-tree page borrowed from the page buffer, only the header is read up front
+#[derive(Debug, Clone, Copy)]
+pub struct PageRef<'a> {
+    page: &'a [u8],
+    header_offset: usize,
+    usable_size: usize,
+    kind: PageKind,
+    cell_count: usize,
+    right_most_pointer: Option<u32>,
+}
+
+impl<'a> PageRef<'a> {
+    /// same arguments as the owned page constructors like `TableBTreeLeafPage::new`
+    pub fn new(page: &'a [u8], header_offset: usize, usable_size: usize) -> Result<PageRef<'a>> {
+        let page = usable_area(page, usable_size);
+        let flag = *page
+            .get(header_offset)
+            .ok_or_else(|| Error::corrupt("b-tree page header runs past end of page"))?;
+        let kind = PageKind::from_flag(flag).ok_or_else(|| Error::corrupt(format!("invalid page type {:#04x}", flag)))?;
+        let cell_count = read_u16(page, &mut (header_offset + 3))? as usize;
+        let right_most_pointer = match kind.is_leaf() {
+            true => None,
+            false => Some(read_u32(page, &mut (header_offset + 8))?),
+        };
+        if header_offset + kind.header_size() + cell_count * 2 > page.len() {
+            return Err(Error::corrupt(format!("cell pointer array of {} cells runs past end of page", cell_count)));
+        }
+        Ok(PageRef {
+            page,
+            header_offset,
+            usable_size,
+            kind,
+            cell_count,
+            right_most_pointer,
+        })
+    }
+
+    pub fn kind(&self) -> PageKind {
+        self.kind
+    }
+
+    pub fn cell_count(&self) -> usize {
+        self.cell_count
+    }
+
+    /// child holding the keys after the last cell, None on leaf pages
+    pub fn right_most_pointer(&self) -> Option<u32> {
+        self.right_most_pointer
+    }
+
+    /// decodes the `index`th cell in key order
+    pub fn cell(&self, index: usize) -> Result<CellRef<'a>> {
+        if index >= self.cell_count {
+            return Err(Error::corrupt(format!("cell {} of a page with {} cells", index, self.cell_count)));
+        }
+        let offset = read_u16(self.page, &mut (self.header_offset + self.kind.header_size() + index * 2))?;
+        CellRef::new(self.page, offset as usize, self.kind, self.usable_size)
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = Result<CellRef<'a>>> + 'a {
+        let page = *self;
+        (0..self.cell_count).map(move |i| page.cell(i))
+    }
+}
+
+//######################################################
+// cell view
+//######################################################
+
+/// one cell of a `PageRef`, the payload is the part stored on the page
+#[derive(Debug, Clone, Copy)]
+pub struct CellRef<'a> {
+    /// set on interior pages
+    pub left_child_pointer: Option<u32>,
+    /// set on table pages
+    pub row_id: Option<i64>,
+    /// total payload size, 0 for table interior cells which have none
+    pub payload_size: u64,
+    pub payload: &'a [u8],
+    /// first page of the overflow chain, 0 when `payload` is complete
+    pub overflow_page: u32,
+    /// the whole cell as stored on the page
+    pub bytes: &'a [u8],
+}
+
+impl<'a> CellRef<'a> {
+    pub fn new(page: &'a [u8], offset: usize, kind: PageKind, usable_size: usize) -> Result<CellRef<'a>> {
+        let start = offset;
+        let mut offset = offset;
+        let left_child_pointer = match kind.is_leaf() {
+            true => None,
+            false => Some(read_u32(page, &mut offset)?),
+        };
+        let mut payload_size: u64 = 0;
+        let mut row_id: u64 = 0;
+        if kind == PageKind::TableInterior {
+            offset += read_var_int(page, offset, &mut row_id)?;
+            return Ok(CellRef {
+                left_child_pointer,
+                row_id: Some(row_id as i64),
+                payload_size,
+                payload: &[],
+                overflow_page: 0,
+                bytes: &page[start..offset],
+            });
+        }
+        offset += read_var_int(page, offset, &mut payload_size)?;
+        if kind == PageKind::TableLeaf {
+            offset += read_var_int(page, offset, &mut row_id)?;
+        }
+
+        let local_size = local_payload_size(payload_size, usable_size, kind == PageKind::TableLeaf);
+        let payload = page
+            .get(offset..offset + local_size)
+            .ok_or_else(|| Error::corrupt(format!("cell payload at offset {} runs past end of page", offset)))?;
+        let mut end = offset + local_size;
+        let overflow_page = if (local_size as u64) < payload_size {
+            read_u32(page, &mut end)?
+        } else {
+            0
+        };
+        Ok(CellRef {
+            left_child_pointer,
+            row_id: kind.is_table().then_some(row_id as i64),
+            payload_size,
+            payload,
+            overflow_page,
+            bytes: &page[start..end],
+        })
+    }
+
+    /// false when the rest of the payload has to be read from overflow pages
+    pub fn is_complete(&self) -> bool {
+        self.payload.len() as u64 == self.payload_size
+    }
+
+    pub fn to_table_leaf_cell(&self) -> TableBTreeLeafCell {
+        TableBTreeLeafCell {
+            payload_size: self.payload_size,
+            row_id: self.row_id.unwrap_or(0),
+            payload: self.payload.to_vec(),
+            overflow_page: self.overflow_page,
+        }
+    }
+
+    pub fn to_table_interior_cell(&self) -> TableBTreeInteriorCell {
+        TableBTreeInteriorCell {
+            left_child_pointer: self.left_child_pointer.unwrap_or(0),
+            row_id: self.row_id.unwrap_or(0),
+        }
+    }
+
+    pub fn to_index_leaf_cell(&self) -> IndexBTreeLeafCell {
+        IndexBTreeLeafCell {
+            payload_size: self.payload_size,
+            payload: self.payload.to_vec(),
+            overflow_page: self.overflow_page,
+        }
+    }
+
+    pub fn to_index_interior_cell(&self) -> IndexBTreeInteriorCell {
+        IndexBTreeInteriorCell {
+            left_child_pointer: self.left_child_pointer.unwrap_or(0),
+            payload_size: self.payload_size,
+            payload: self.payload.to_vec(),
+            overflow_page: self.overflow_page,
+        }
+    }
+}
+
+//######################################################
+// value view
+//######################################################
+
+/// record value borrowing text and blob bytes from the payload
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueRef<'a> {
+    Null,
+    Integer(i64),
+    Real(f64),
+    /// bytes as stored, in the database text encoding
+    Text(&'a [u8], TextEncoding),
+    Blob(&'a [u8]),
+}
+
+impl<'a> ValueRef<'a> {
+    /// decodes the value of `serial_type` at `offset` and moves `offset` past it
+    pub fn read(serial_type: u64, buf: &'a [u8], offset: &mut usize, encoding: TextEncoding) -> Result<ValueRef<'a>> {
+        let value = match serial_type {
+            0 => ValueRef::Null,
+            1 => ValueRef::Integer(read_signed_int(buf, offset, 1)?),
+            2 => ValueRef::Integer(read_signed_int(buf, offset, 2)?),
+            3 => ValueRef::Integer(read_signed_int(buf, offset, 3)?),
+            4 => ValueRef::Integer(read_signed_int(buf, offset, 4)?),
+            5 => ValueRef::Integer(read_signed_int(buf, offset, 6)?),
+            6 => ValueRef::Integer(read_signed_int(buf, offset, 8)?),
+            7 => ValueRef::Real(f64::from_bits(read_signed_int(buf, offset, 8)? as u64)),
+            8 => ValueRef::Integer(0),
+            9 => ValueRef::Integer(1),
+            10 | 11 => {
+                return Err(Error::corrupt(format!("reserved record serial type {}", serial_type)))
+            }
+            x => {
+                let size = if x % 2 == 0 { (x - 12) / 2 } else { (x - 13) / 2 };
+                let data = buf
+                    .get(*offset..*offset + size as usize)
+                    .ok_or_else(|| Error::corrupt(format!("record value of {} bytes runs past end of payload", size)))?;
+                *offset += size as usize;
+                if x % 2 == 0 {
+                    ValueRef::Blob(data)
+                } else {
+                    ValueRef::Text(data, encoding)
+                }
+            }
+        };
+        Ok(value)
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            ValueRef::Integer(i) => Some(i),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            ValueRef::Real(r) => Some(r),
+            _ => None,
+        }
+    }
+
+    /// raw bytes of text and blob values
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match *self {
+            ValueRef::Text(data, _) | ValueRef::Blob(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// borrowed when the text is valid UTF-8, decoded otherwise
+    pub fn text(&self) -> Option<Cow<'a, str>> {
+        match *self {
+            ValueRef::Text(data, TextEncoding::Utf8) => match std::str::from_utf8(data) {
+                Ok(s) => Some(Cow::Borrowed(s)),
+                Err(_) => Some(Cow::Owned(TextEncoding::Utf8.decode(data))),
+            },
+            ValueRef::Text(data, encoding) => Some(Cow::Owned(encoding.decode(data))),
+            _ => None,
+        }
+    }
+
+    pub fn to_column(&self) -> Column {
+        match *self {
+            ValueRef::Null => Column::NULL,
+            ValueRef::Integer(i) => Column::I64(i),
+            ValueRef::Real(r) => Column::F64(r),
+            ValueRef::Text(data, encoding) => Column::Text(Text {
+                size: data.len() as u64,
+                value: encoding.decode(data),
+            }),
+            ValueRef::Blob(data) => Column::Blob(Blob {
+                size: data.len() as u64,
+                data: data.to_vec(),
+            }),
+        }
+    }
+}
+
+/// every value of the record in `buf`, which must hold the complete payload
+pub fn read_values(buf: &[u8], encoding: TextEncoding) -> Result<Vec<ValueRef<'_>>> {
+    RecordView::new(buf, encoding)?.values()
+}
+
+//######################################################
+// record view
+//######################################################
+
+/// record with its header parsed once, column values are only decoded when read
+#[derive(Debug, Clone)]
+pub struct RecordView<'a> {
+    buf: &'a [u8],
+    encoding: TextEncoding,
+    header_size: u64,
+    serial_types: Vec<u64>,
+    /// start of every value in `buf`
+    offsets: Vec<usize>,
+}
+
+impl<'a> RecordView<'a> {
+    /// `buf` must hold the complete payload, including any overflow
+    pub fn new(buf: &'a [u8], encoding: TextEncoding) -> Result<RecordView<'a>> {
+        let mut offset = 0;
+        let mut header_size: u64 = 0;
+        offset += read_var_int(buf, offset, &mut header_size)?;
+        if header_size > buf.len() as u64 {
+            return Err(Error::corrupt(format!("record header size {} exceeds payload", header_size)));
+        }
+        let mut serial_types: Vec<u64> = vec![];
+        let mut offsets: Vec<usize> = vec![];
+        let mut body_offset = header_size as usize;
+        while offset < header_size as usize {
+            let mut serial_type: u64 = 0;
+            offset += read_var_int(buf, offset, &mut serial_type)?;
+            serial_types.push(serial_type);
+            offsets.push(body_offset);
+            body_offset = body_offset.saturating_add(serial_type_size(serial_type)?);
+        }
+        if body_offset > buf.len() {
+            return Err(Error::corrupt(format!(
+                "record body of {} bytes runs past end of payload",
+                body_offset - header_size as usize
+            )));
+        }
+        Ok(RecordView {
+            buf,
+            encoding,
+            header_size,
+            serial_types,
+            offsets,
+        })
+    }
+
+    pub fn header_size(&self) -> u64 {
+        self.header_size
+    }
+
+    /// number of values stored in the record
+    pub fn len(&self) -> usize {
+        self.serial_types.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.serial_types.is_empty()
+    }
+
+    pub fn serial_type(&self, index: usize) -> Option<u64> {
+        self.serial_types.get(index).copied()
+    }
+
+    /// decodes the `index`th value, None past the end of records that are
+    /// shorter than their table, like rows written before an ALTER TABLE ADD COLUMN
+    pub fn get(&self, index: usize) -> Result<Option<ValueRef<'a>>> {
+        let serial_type = match self.serial_types.get(index) {
+            Some(t) => *t,
+            None => return Ok(None),
+        };
+        let mut offset = self.offsets[index];
+        ValueRef::read(serial_type, self.buf, &mut offset, self.encoding).map(Some)
+    }
+
+    pub fn values(&self) -> Result<Vec<ValueRef<'a>>> {
+        (0..self.len())
+            .map(|i| self.get(i).map(|v| v.unwrap_or(ValueRef::Null)))
+            .collect()
+    }
+
+    pub fn to_record(&self) -> Result<Record> {
+        Ok(Record {
+            header_size: self.header_size,
+            body: self.values()?.iter().map(ValueRef::to_column).collect(),
+        })
+    }
+}
+
+/// bytes taken up in the record body by a value of `serial_type`
+pub fn serial_type_size(serial_type: u64) -> Result<usize> {
+    let size = match serial_type {
+        0 | 8 | 9 => 0,
+        1 => 1,
+        2 => 2,
+        3 => 3,
+        4 => 4,
+        5 => 6,
+        6 | 7 => 8,
+        10 | 11 => return Err(Error::corrupt(format!("reserved record serial type {}", serial_type))),
+        x => ((x - 12) / 2) as usize,
+    };
+    Ok(size)
+}
+
+/// big-endian two's complement integer of `size` bytes
+fn read_signed_int(buf: &[u8], offset: &mut usize, size: usize) -> Result<i64> {
+    let bytes = buf
+        .get(*offset..*offset + size)
+        .ok_or_else(|| Error::corrupt(format!("record value of {} bytes runs past end of payload", size)))?;
+    // start from all ones for negative values so the sign extends over the missing high bytes
+    let init: i64 = if bytes[0] & 0x80 != 0 { -1 } else { 0 };
+    let val = bytes.iter().fold(init, |acc, &b| (acc << 8) | b as i64);
+    *offset += size;
+    Ok(val)
+}
@@ -0,0 +1,462 @@
+use crate::tools::{
+    error::{Error, Result},
+    helper::read_u32,
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+pub const WAL_HEADER_SIZE: usize = 32;
+pub const WAL_FRAME_HEADER_SIZE: usize = 24;
+const WAL_MAGIC: u32 = 0x377f0682;
+const WAL_VERSION: u32 = 3007000;
+
+/// the two running sums of a log checksum
+type Checksum = (u32, u32);
+
+//######################################################
+// wal
+//######################################################
+
+/// committed content of a `-wal` file. The wal-index in `-shm` is not used,
+/// the frame map is rebuilt by scanning the log like sqlite does on recovery.
+pub struct Wal {
+    file: File,
+    page_size: usize,
+    /// page number to file offset of the page image in its latest committed frame
+    frames: HashMap<u32, u64>,
+    /// database size in pages after the last commit, 0 without any commit
+    database_size: u32,
+    big_endian: bool,
+    /// checkpoint sequence and salts, sqlite changes them whenever it restarts the log
+    checkpoint_sequence: u32,
+    salt: (u32, u32),
+    /// end of the last valid frame, committed or not, and the running checksum there
+    end: u64,
+    checksum: Checksum,
+}
+
+impl Wal {
+    /// returns None when there is no log or it holds nothing usable,
+    /// an invalid header means sqlite would ignore the file as well
+    pub fn open<P: AsRef<Path>>(path: P, page_size: usize) -> Result<Option<Wal>> {
+        let mut file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut data = vec![];
+        file.read_to_end(&mut data)?;
+        if data.len() < WAL_HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let header = &data[..WAL_HEADER_SIZE];
+        let magic = read_u32(header, &mut 0)?;
+        if magic & !1 != WAL_MAGIC {
+            return Ok(None);
+        }
+        // the low bit selects the byte order of the checksummed words
+        let big_endian = magic & 1 == 1;
+        let version = read_u32(header, &mut 4)?;
+        if version != WAL_VERSION {
+            return Err(Error::UnsupportedFeature(format!("wal format version {}", version)));
+        }
+        let wal_page_size = match read_u32(header, &mut 8)? {
+            65536 => 65536,
+            x if (512..=32768).contains(&x) && x.is_power_of_two() => x as usize,
+            _ => return Ok(None),
+        };
+        if wal_page_size != page_size {
+            return Ok(None);
+        }
+        let checkpoint_sequence = read_u32(header, &mut 12)?;
+        let salt = (read_u32(header, &mut 16)?, read_u32(header, &mut 20)?);
+        let mut checksum = wal_checksum(big_endian, (0, 0), &header[..24]);
+        if checksum != (read_u32(header, &mut 24)?, read_u32(header, &mut 28)?) {
+            return Ok(None);
+        }
+
+        let mut frames = HashMap::new();
+        let mut pending: Vec<(u32, u64)> = vec![];
+        let mut database_size = 0;
+        let frame_size = WAL_FRAME_HEADER_SIZE + page_size;
+        let mut offset = WAL_HEADER_SIZE;
+        // frames after the first one that fails validation are leftovers of an older log
+        while offset + frame_size <= data.len() {
+            let Some((page_number, commit_size, next)) =
+                check_frame(big_endian, salt, checksum, &data[offset..offset + frame_size])?
+            else {
+                break;
+            };
+            checksum = next;
+            pending.push((page_number, (offset + WAL_FRAME_HEADER_SIZE) as u64));
+            if commit_size != 0 {
+                frames.extend(pending.drain(..));
+                database_size = commit_size;
+            }
+            offset += frame_size;
+        }
+        if frames.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Wal {
+            file,
+            page_size,
+            frames,
+            database_size,
+            big_endian,
+            checkpoint_sequence,
+            salt,
+            end: offset as u64,
+            checksum,
+        }))
+    }
+
+    /// true when the log at `path` is no longer the one read here: sqlite restarted it,
+    /// which keeps the file length but changes the salts, or a frame follows the last one
+    pub fn changed<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        let mut file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(e.into()),
+        };
+        let mut header = [0u8; WAL_HEADER_SIZE];
+        if !read_all(&mut file, &mut header)? {
+            return Ok(true);
+        }
+        let salt = (read_u32(&header, &mut 16)?, read_u32(&header, &mut 20)?);
+        if (read_u32(&header, &mut 12)?, salt) != (self.checkpoint_sequence, self.salt) {
+            return Ok(true);
+        }
+        let mut frame = vec![0u8; WAL_FRAME_HEADER_SIZE + self.page_size];
+        file.seek(SeekFrom::Start(self.end))?;
+        if !read_all(&mut file, &mut frame)? {
+            return Ok(false);
+        }
+        Ok(check_frame(self.big_endian, self.salt, self.checksum, &frame)?.is_some())
+    }
+
+    pub fn contains(&self, page: u32) -> bool {
+        self.frames.contains_key(&page)
+    }
+
+    pub fn database_size(&self) -> u32 {
+        self.database_size
+    }
+
+    /// latest committed image of `page`, None when the main file copy is current
+    pub fn read_page(&mut self, page: u32) -> Result<Option<Vec<u8>>> {
+        let offset = match self.frames.get(&page) {
+            Some(o) => *o,
+            None => return Ok(None),
+        };
+        let mut buf = vec![0u8; self.page_size];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut buf)?;
+        Ok(Some(buf))
+    }
+}
+
+/// page number, commit size and the running checksum after `frame` when it belongs to
+/// the log with `salt` and continues the checksum `start`, None otherwise
+fn check_frame(big_endian: bool, salt: (u32, u32), start: Checksum, frame: &[u8]) -> Result<Option<(u32, u32, Checksum)>> {
+    let (header, page) = frame.split_at(WAL_FRAME_HEADER_SIZE);
+    let page_number = read_u32(header, &mut 0)?;
+    let commit_size = read_u32(header, &mut 4)?;
+    if page_number == 0 || salt != (read_u32(header, &mut 8)?, read_u32(header, &mut 12)?) {
+        return Ok(None);
+    }
+    let checksum = wal_checksum(big_endian, start, &header[..8]);
+    let checksum = wal_checksum(big_endian, checksum, page);
+    if checksum != (read_u32(header, &mut 16)?, read_u32(header, &mut 20)?) {
+        return Ok(None);
+    }
+    Ok(Some((page_number, commit_size, checksum)))
+}
+
+/// fills `buf`, false when the file ends before
+fn read_all(file: &mut File, buf: &mut [u8]) -> Result<bool> {
+    match file.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// sqlite's running checksum over pairs of 32-bit words
+pub fn wal_checksum(big_endian: bool, start: (u32, u32), data: &[u8]) -> (u32, u32) {
+    let (mut s0, mut s1) = start;
+    for pair in data.chunks_exact(8) {
+        let word = |b: &[u8]| {
+            let b = [b[0], b[1], b[2], b[3]];
+            if big_endian {
+                u32::from_be_bytes(b)
+            } else {
+                u32::from_le_bytes(b)
+            }
+        };
+        s0 = s0.wrapping_add(word(&pair[..4])).wrapping_add(s1);
+        s1 = s1.wrapping_add(word(&pair[4..])).wrapping_add(s0);
+    }
+    (s0, s1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::{
+        database::Database,
+        defs::{Column, Record},
+        helper::write_u32,
+        pager::DatabaseOptions,
+        testing::TempDb,
+    };
+    use std::fs;
+
+    const PAGE_SIZE: usize = 512;
+    const SALT: (u32, u32) = (0x1234_5678, 0x9abc_def0);
+
+    /// builds a log frame by frame, with checksums in the order chosen by the magic
+    struct Log {
+        data: Vec<u8>,
+        big_endian: bool,
+        salt: (u32, u32),
+        checksum: (u32, u32),
+    }
+
+    impl Log {
+        fn new(big_endian: bool) -> Log {
+            Log::restarted(big_endian, 0, SALT)
+        }
+
+        /// the log as sqlite starts it over after a checkpoint
+        fn restarted(big_endian: bool, checkpoint_sequence: u32, salt: (u32, u32)) -> Log {
+            let mut data = vec![0u8; WAL_HEADER_SIZE];
+            write_u32(&mut data, 0, WAL_MAGIC | big_endian as u32);
+            write_u32(&mut data, 4, WAL_VERSION);
+            write_u32(&mut data, 8, PAGE_SIZE as u32);
+            write_u32(&mut data, 12, checkpoint_sequence);
+            write_u32(&mut data, 16, salt.0);
+            write_u32(&mut data, 20, salt.1);
+            let checksum = wal_checksum(big_endian, (0, 0), &data[..24]);
+            write_u32(&mut data, 24, checksum.0);
+            write_u32(&mut data, 28, checksum.1);
+            Log {
+                data,
+                big_endian,
+                salt,
+                checksum,
+            }
+        }
+
+        /// appends a frame for `page`, `commit_size` is 0 except on the last frame of a transaction
+        fn frame(&mut self, page_number: u32, commit_size: u32, page: &[u8]) -> &mut Log {
+            let mut header = vec![0u8; WAL_FRAME_HEADER_SIZE];
+            write_u32(&mut header, 0, page_number);
+            write_u32(&mut header, 4, commit_size);
+            write_u32(&mut header, 8, self.salt.0);
+            write_u32(&mut header, 12, self.salt.1);
+            self.checksum = wal_checksum(self.big_endian, self.checksum, &header[..8]);
+            self.checksum = wal_checksum(self.big_endian, self.checksum, page);
+            write_u32(&mut header, 16, self.checksum.0);
+            write_u32(&mut header, 20, self.checksum.1);
+            self.data.extend_from_slice(&header);
+            self.data.extend_from_slice(page);
+            self
+        }
+
+        /// offset of the frame header of frame `index`
+        fn frame_offset(index: usize) -> usize {
+            WAL_HEADER_SIZE + index * (WAL_FRAME_HEADER_SIZE + PAGE_SIZE)
+        }
+    }
+
+    fn page(fill: u8) -> Vec<u8> {
+        vec![fill; PAGE_SIZE]
+    }
+
+    fn open(temp: &TempDb, data: &[u8]) -> Option<Wal> {
+        let path = temp.sibling("-wal");
+        fs::write(&path, data).unwrap();
+        Wal::open(path, PAGE_SIZE).unwrap()
+    }
+
+    fn read(wal: &mut Wal, page: u32) -> Option<u8> {
+        wal.read_page(page).unwrap().map(|p| p[0])
+    }
+
+    #[test]
+    fn header_checksum_matches_sqlite() {
+        // header of a log written by sqlite 3 on a little-endian machine
+        let header = [
+            0x37, 0x7f, 0x06, 0x82, 0x00, 0x2d, 0xe2, 0x18, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0xb8, 0x3b, 0x0c, 0xf7, 0x38, 0x6b, 0x7b, 0x03, 0xcb, 0xd7, 0x3e, 0xcb, 0x87, 0x2c, 0x81, 0xa8,
+        ];
+        let checksum = wal_checksum(false, (0, 0), &header[..24]);
+        assert_eq!(checksum, (read_u32(&header, &mut 24).unwrap(), read_u32(&header, &mut 28).unwrap()));
+    }
+
+    #[test]
+    fn only_committed_frames_are_read() {
+        let temp = TempDb::create("wal-committed", PAGE_SIZE, &[]);
+        for big_endian in [false, true] {
+            let mut log = Log::new(big_endian);
+            log.frame(2, 0, &page(1))
+                .frame(3, 3, &page(2))
+                .frame(2, 0, &page(3))
+                .frame(2, 4, &page(4))
+                // a transaction that never committed
+                .frame(3, 0, &page(5));
+            let mut wal = open(&temp, &log.data).unwrap();
+            assert_eq!(wal.database_size(), 4);
+            assert_eq!(read(&mut wal, 2), Some(4), "the latest commit wins");
+            assert_eq!(read(&mut wal, 3), Some(2));
+            assert_eq!(read(&mut wal, 1), None);
+            assert!(!wal.contains(4));
+        }
+    }
+
+    #[test]
+    fn scan_stops_at_the_first_invalid_frame() {
+        let temp = TempDb::create("wal-invalid", PAGE_SIZE, &[]);
+        let mut log = Log::new(true);
+        log.frame(2, 2, &page(1)).frame(2, 2, &page(2)).frame(3, 3, &page(3));
+
+        // a flipped bit in the page breaks the running checksum from there on
+        let mut data = log.data.clone();
+        data[Log::frame_offset(1) + WAL_FRAME_HEADER_SIZE + 100] ^= 1;
+        let mut wal = open(&temp, &data).unwrap();
+        assert_eq!(wal.database_size(), 2);
+        assert_eq!(read(&mut wal, 2), Some(1));
+        assert!(!wal.contains(3));
+
+        // frames of an older log carry other salts
+        let mut data = log.data.clone();
+        write_u32(&mut data, Log::frame_offset(1) + 8, SALT.0 + 1);
+        let mut wal = open(&temp, &data).unwrap();
+        assert_eq!(read(&mut wal, 2), Some(1));
+        assert!(!wal.contains(3));
+
+        // a torn last frame
+        let data = &log.data[..log.data.len() - 1];
+        let mut wal = open(&temp, data).unwrap();
+        assert_eq!(read(&mut wal, 2), Some(2));
+        assert!(!wal.contains(3));
+    }
+
+    #[test]
+    fn unusable_logs_are_ignored() {
+        let temp = TempDb::create("wal-unusable", PAGE_SIZE, &[]);
+        assert!(Wal::open(temp.sibling("-wal"), PAGE_SIZE).unwrap().is_none());
+        let mut log = Log::new(false);
+        log.frame(2, 2, &page(1));
+        assert!(open(&temp, &log.data).is_some());
+
+        assert!(open(&temp, &log.data[..WAL_HEADER_SIZE - 1]).is_none());
+        assert!(open(&temp, &log.data[..WAL_HEADER_SIZE]).is_none());
+        // magic, page size and header checksum
+        for offset in [0, 10, 24] {
+            let mut data = log.data.clone();
+            data[offset] ^= 0x40;
+            assert!(open(&temp, &data).is_none(), "byte {} changed", offset);
+        }
+        let path = temp.sibling("-wal");
+        assert!(Wal::open(&path, PAGE_SIZE * 2).unwrap().is_none());
+
+        let mut uncommitted = Log::new(false);
+        uncommitted.frame(2, 0, &page(1));
+        assert!(open(&temp, &uncommitted.data).is_none());
+
+        let mut data = log.data.clone();
+        write_u32(&mut data, 4, WAL_VERSION + 1);
+        fs::write(&path, data).unwrap();
+        assert!(matches!(Wal::open(&path, PAGE_SIZE), Err(Error::UnsupportedFeature(_))));
+    }
+
+    #[test]
+    fn new_frames_and_restarts_are_changes() {
+        let temp = TempDb::create("wal-changed", PAGE_SIZE, &[]);
+        let path = temp.sibling("-wal");
+        let mut log = Log::new(false);
+        log.frame(2, 2, &page(1)).frame(3, 0, &page(2));
+        let wal = open(&temp, &log.data).unwrap();
+        assert!(!wal.changed(&path).unwrap());
+
+        // neither a torn frame nor one of an older log is new
+        let mut older = Log::restarted(false, 0, (SALT.0 - 1, SALT.1));
+        older.frame(2, 2, &page(3)).frame(2, 2, &page(3)).frame(2, 2, &page(3));
+        let mut data = log.data.clone();
+        data.extend_from_slice(&older.data[Log::frame_offset(2)..]);
+        fs::write(&path, &data).unwrap();
+        assert!(!wal.changed(&path).unwrap());
+        log.frame(3, 3, &page(4));
+        fs::write(&path, &log.data[..log.data.len() - 1]).unwrap();
+        assert!(!wal.changed(&path).unwrap());
+        // the commit that follows the uncommitted frame
+        fs::write(&path, &log.data).unwrap();
+        assert!(wal.changed(&path).unwrap());
+
+        // a restart writes over the old frames and keeps the file length
+        let wal = Wal::open(&path, PAGE_SIZE).unwrap().unwrap();
+        assert!(!wal.changed(&path).unwrap());
+        let mut restarted = Log::restarted(false, 1, (SALT.0 + 1, 7));
+        restarted.frame(2, 2, &page(5));
+        let mut data = restarted.data.clone();
+        data.extend_from_slice(&log.data[data.len()..]);
+        assert_eq!(data.len(), log.data.len());
+        fs::write(&path, &data).unwrap();
+        assert!(wal.changed(&path).unwrap());
+
+        fs::remove_file(&path).unwrap();
+        assert!(wal.changed(&path).unwrap());
+    }
+
+    #[test]
+    fn database_reads_pages_from_the_log() {
+        let table = ("t", "CREATE TABLE t(a)");
+        let row = |v: i64| Record {
+            header_size: 0,
+            body: vec![Column::I64(v)],
+        };
+        // the newer version of the table page comes from a second database
+        let newer = TempDb::create("wal-newer", PAGE_SIZE, &[table]);
+        let mut db = newer.open();
+        for v in [10, 20, 30] {
+            db.insert("t", v, &row(v)).unwrap();
+        }
+        let table_page = db.pager().read_raw_page(2).unwrap().to_vec();
+        drop(db);
+
+        let temp = TempDb::create("wal-reads", PAGE_SIZE, &[table]);
+        let mut db = temp.open();
+        db.insert("t", 10, &row(10)).unwrap();
+        drop(db);
+        // read and write versions 2 put the database in WAL mode
+        let mut data = fs::read(&temp.path).unwrap();
+        data[18] = 2;
+        data[19] = 2;
+        fs::write(&temp.path, data).unwrap();
+        let mut log = Log::new(false);
+        log.frame(2, 2, &table_page);
+        fs::write(temp.sibling("-wal"), &log.data).unwrap();
+
+        let mut db = Database::open_with_options(&temp.path, &DatabaseOptions::default()).unwrap();
+        let rows: Vec<i64> = db.read_table("t").unwrap().iter().map(|c| c.row_id).collect();
+        assert_eq!(rows, vec![10, 20, 30]);
+        assert!(matches!(db.insert("t", 40, &row(40)), Err(Error::UnsupportedFeature(_))));
+
+        // a restarted log of the same length with the table page of the main file
+        let main_page = fs::read(&temp.path).unwrap()[PAGE_SIZE..2 * PAGE_SIZE].to_vec();
+        let mut restarted = Log::restarted(false, 1, (SALT.0 + 1, SALT.1));
+        restarted.frame(2, 2, &main_page);
+        assert_eq!(restarted.data.len(), log.data.len());
+        fs::write(temp.sibling("-wal"), &restarted.data).unwrap();
+        assert!(db.pager().check_for_changes().unwrap());
+        let rows: Vec<i64> = db.read_table("t").unwrap().iter().map(|c| c.row_id).collect();
+        assert_eq!(rows, vec![10]);
+    }
+}